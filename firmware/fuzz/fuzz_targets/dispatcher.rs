@@ -0,0 +1,140 @@
+//! Random byte streams through the command processing
+//!
+//! The json and SCPI lines are parsed and dispatched on simulated pins.
+//! Checks that no stream panics and that a test command is still answered
+//! after any garbage.
+
+#![no_main]
+
+use std::cell::Cell;
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+
+use picoha_io::processor::{CommandProcessor, ProcessorState};
+use picoha_io::processor::pins::{PinBackend, PinMode};
+use picoha_io::processor::protocol::{Answer, AnswerStatus, AnswerText, Command, CommandCode};
+use picoha_io::processor::protocol::{DataAnswer, InfoAnswer, Response};
+use picoha_io::processor::scpi::ScpiReply;
+use picoha_io::processor::sim_pins::{SimPins, SIM_PIN_COUNT};
+
+// ============================================================================
+
+/// Device without hardware commands, on simulated pins
+struct FuzzDevice {
+    state: ProcessorState,
+    pins: SimPins,
+    now: Cell<u64>,
+}
+
+impl FuzzDevice {
+    fn new() -> Self {
+        let mut pins = SimPins::new();
+        pins.connect(0, 1).unwrap();
+        pins.connect(2, 3).unwrap();
+        Self {
+            state: ProcessorState::new(),
+            pins: pins,
+            now: Cell::new(0),
+        }
+    }
+
+    /// Process all the complete lines, the responses are serialized like on
+    /// the device
+    fn drain(&mut self) -> Option<Response> {
+        let mut last = None;
+        let mut ans_buffer = [0u8; 4096];
+        while let Some(response) = self.update_command_processing() {
+            if !matches!(response, Response::Scpi(_)) {
+                serde_json_core::to_slice(&response, &mut ans_buffer).ok();
+            }
+            last = Some(response);
+        }
+        last
+    }
+}
+
+impl CommandProcessor for FuzzDevice {
+    type Pins = SimPins;
+
+    fn state(&self) -> &ProcessorState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut ProcessorState {
+        &mut self.state
+    }
+
+    fn pins(&mut self) -> &mut SimPins {
+        &mut self.pins
+    }
+
+    fn now_us(&self) -> u64 {
+        // Fast time, the long waits end quickly
+        self.now.set(self.now.get() + 1_000);
+        self.now.get()
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.now.set(self.now.get() + us as u64);
+    }
+
+    fn apply_safe_state(&mut self) {
+        for pin in 0..SIM_PIN_COUNT as u8 {
+            self.pins.set_mode(pin, PinMode::PullDownInput).ok();
+        }
+    }
+
+    fn process_test(&self) -> InfoAnswer {
+        InfoAnswer {
+            sts: AnswerStatus::Ok,
+            pin: 0,
+            arg: 1,
+            msg: AnswerText::new(),
+            lbl: heapless::String::new(),
+            ser: heapless::String::from("FUZZ"),
+        }
+    }
+
+    fn write_identification(&self, reply: &mut ScpiReply) {
+        reply.push_str("fuzz,picoha-io,FUZZ,0").ok();
+    }
+
+    fn execute_device_command(&mut self, _code: CommandCode, _cmd: &Command) -> Answer {
+        Answer::error(0, 0, AnswerText::from_str("Not available").unwrap())
+    }
+
+    fn process_data_command(&mut self, _code: CommandCode, _cmd: &Command) -> DataAnswer {
+        DataAnswer::from(Answer::error(0, 0, AnswerText::from_str("Not available").unwrap()))
+    }
+}
+
+// ============================================================================
+
+fuzz_target!(|mut data: &[u8]| {
+    let mut device = FuzzDevice::new();
+
+    // Each chunk starts with a header byte: the low bits give its size, the
+    // high bit processes the complete lines once it is loaded
+    while let Some((&header, rest)) = data.split_first() {
+        let (chunk, rest) = rest.split_at(((header & 0x7F) as usize).min(rest.len()));
+        device.feed_cmd_buffer(chunk, chunk.len());
+        if header & 0x80 != 0 {
+            device.drain();
+        }
+        data = rest;
+    }
+
+    // End the pending line and go back to json, whatever the protocol and
+    // the crc mode selected by the stream
+    device.drain();
+    device.feed_cmd_buffer(b"\n", 1);
+    device.drain();
+    device.feed_cmd_buffer(b"SYST:PROT JSON\n", 15);
+    device.drain();
+
+    let test = Command { cod: 10, pin: 0, arg: 0, val: None, prm: None, dat: None, crc: None };
+    let line = format!("{{\"cod\":10,\"pin\":0,\"arg\":0,\"crc\":{}}}\n", test.payload_crc());
+    device.feed_cmd_buffer(line.as_bytes(), line.len());
+    assert!(matches!(device.drain(), Some(Response::Info(_))), "test command not answered");
+});
@@ -0,0 +1,67 @@
+//! Random byte streams through the framing of the command lines
+//!
+//! Checks that no stream panics, that the commands are whole lines of the
+//! stream and that the framing resynchronises after any garbage.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use picoha_io::processor::CMD_BUFFER_SIZE;
+use picoha_io::processor::buffer::UsbBuffer;
+
+// ============================================================================
+
+/// Get all the complete commands of the buffer
+fn drain<const CAPACITY: usize>(buffer: &mut UsbBuffer<CAPACITY>, commands: &mut Vec<Vec<u8>>) {
+    let mut dest = [0u8; CAPACITY];
+    while let Some(size) = buffer.get_command(&mut dest) {
+        commands.push(dest[0..size].to_vec());
+    }
+}
+
+/// Feed the stream to the buffer, chunk by chunk
+///
+/// Each chunk starts with a header byte: the low bits give its size, the high
+/// bit drains the complete commands once it is loaded.
+fn check<const CAPACITY: usize>(mut data: &[u8]) {
+    let mut buffer = UsbBuffer::<CAPACITY>::new();
+    let mut stream = Vec::new();
+    let mut commands = Vec::new();
+
+    while let Some((&header, rest)) = data.split_first() {
+        let (chunk, rest) = rest.split_at(((header & 0x7F) as usize).min(rest.len()));
+        buffer.load(chunk, chunk.len());
+        stream.extend_from_slice(chunk);
+        if header & 0x80 != 0 {
+            drain(&mut buffer, &mut commands);
+        }
+        data = rest;
+    }
+
+    // End the pending line, then a known command must come out alone
+    drain(&mut buffer, &mut commands);
+    buffer.load(b"\n", 1);
+    stream.push(b'\n');
+    drain(&mut buffer, &mut commands);
+
+    let mut sync = Vec::new();
+    buffer.load(b"SYNC\n", 5);
+    drain(&mut buffer, &mut sync);
+    assert_eq!(sync, [b"SYNC".to_vec()], "framing not resynchronised");
+
+    // Commands are whole lines of the stream, in order
+    let mut lines = stream.split(|&c| c == b'\n');
+    for command in commands.iter() {
+        assert!(command.len() < CAPACITY);
+        assert!(lines.any(|line| line == &command[..]), "command is not a line of the stream");
+    }
+}
+
+// ============================================================================
+
+fuzz_target!(|data: &[u8]| {
+    // Small buffer to reach the overflows quickly, then the device one
+    check::<64>(data);
+    check::<CMD_BUFFER_SIZE>(data);
+});
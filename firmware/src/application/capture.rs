@@ -0,0 +1,321 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::PIO0;
+use rp_pico::hal::pio::{Buffers, ShiftDirection, PIO, SM2};
+
+// PIO assembler
+use pio::{Assembler, InSource, JmpCondition, MovDestination, MovOperation, MovSource, OutDestination, WaitSource};
+
+use super::dma_ctrl;
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Max number of samples in a capture
+pub const CAPTURE_MAX_SAMPLES: usize = 16384;
+
+/// Max number of raw bytes sent in a chunk
+pub const CHUNK_BYTES: usize = 768;
+
+/// Index of the capture state machine in pio0
+const CAPTURE_SM_INDEX: usize = 2;
+
+/// Number of gpios that can trigger the capture
+const TRIGGER_PIN_COUNT: u8 = 30;
+
+/// Capture buffer, out of the stack
+static mut CAPTURE_BUFFER: [u32; CAPTURE_MAX_SAMPLES] = [0; CAPTURE_MAX_SAMPLES];
+
+// ============================================================================
+
+/// Condition that starts the sampling
+pub enum CaptureTrigger {
+    /// Start immediately
+    Immediate,
+
+    /// Start on a rising edge of the pin
+    Rising(u8),
+
+    /// Start on a falling edge of the pin
+    Falling(u8),
+
+    /// Start when the pins under the (contiguous) mask match the value
+    Pattern { mask: u32, value: u32 },
+}
+
+/// State of the capture
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaptureState {
+    Idle    = 0,
+    Running = 1,
+    Done    = 2,
+}
+
+/// Errors of the capture
+pub enum CaptureError {
+    /// Cannot start the state machine
+    Pio(PioError),
+
+    /// The sample rate cannot be reached
+    InvalidRate,
+
+    /// The sample count is 0 or too large
+    InvalidCount,
+
+    /// The pattern mask must be a non empty set of contiguous pins
+    InvalidPattern,
+
+    /// The edge trigger pin is not a gpio
+    InvalidTriggerPin,
+
+    /// The requested chunk is out of the captured data
+    InvalidChunk,
+}
+
+// ============================================================================
+
+/// Samples the gpios into RAM with a pio state machine and a DMA channel
+///
+/// Each sample is the 32 bits value of the gpio bank. Samples are sent to the
+/// host packed on 1, 2 or 4 bytes depending on the span of the pin mask.
+pub struct LogicCapture {
+    /// State machine used to sample the pins
+    slot: PioSlot<PIO0, SM2>,
+
+    /// Samples
+    buffer: &'static mut [u32; CAPTURE_MAX_SAMPLES],
+
+    /// Pins kept in the samples
+    pin_mask: u32,
+
+    /// Sampling rate in Hz
+    sample_rate: u32,
+
+    /// Number of samples requested
+    samples: u32,
+
+    /// Number of samples available once the capture is finished
+    captured: u32,
+
+    /// Current state
+    state: CaptureState,
+}
+
+// ============================================================================
+
+impl LogicCapture {
+    ///
+    pub fn new(slot: PioSlot<PIO0, SM2>) -> Self {
+        Self {
+            slot: slot,
+            // Safety: the buffer is only borrowed here, by the single instance
+            buffer: unsafe { &mut CAPTURE_BUFFER },
+            pin_mask: 0,
+            sample_rate: 0,
+            samples: 0,
+            captured: 0,
+            state: CaptureState::Idle,
+        }
+    }
+
+    /// Start a capture
+    pub fn start(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        dma: &pac::DMA,
+        sys_clk_hz: u32,
+        pin_mask: u32,
+        sample_rate: u32,
+        samples: u32,
+        trigger: CaptureTrigger,
+    ) -> Result<(), CaptureError> {
+        if self.slot.is_active() {
+            return Err(CaptureError::Pio(PioError::Busy));
+        }
+
+        if samples == 0 || samples as usize > CAPTURE_MAX_SAMPLES || pin_mask == 0 {
+            return Err(CaptureError::InvalidCount);
+        }
+
+        // The state machine samples once per cycle
+        if sample_rate == 0 || sample_rate > sys_clk_hz / 2 {
+            return Err(CaptureError::InvalidRate);
+        }
+        let divisor = sys_clk_hz as f32 / sample_rate as f32;
+        if divisor >= 65536.0 {
+            return Err(CaptureError::InvalidRate);
+        }
+
+        match trigger {
+            CaptureTrigger::Rising(pin) | CaptureTrigger::Falling(pin) if pin >= TRIGGER_PIN_COUNT => {
+                return Err(CaptureError::InvalidTriggerPin);
+            },
+            _ => {},
+        }
+
+        // Build the program around the trigger
+        let mut a = Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
+        let mut pattern: u32 = 0;
+
+        a.pull(false, true); // wait for the dma to be ready
+        a.mov(MovDestination::Y, MovOperation::None, MovSource::OSR);
+        match trigger {
+            CaptureTrigger::Immediate => {},
+
+            CaptureTrigger::Rising(pin) => {
+                a.wait(0, WaitSource::GPIO, pin, false);
+                a.wait(1, WaitSource::GPIO, pin, false);
+            },
+
+            CaptureTrigger::Falling(pin) => {
+                a.wait(1, WaitSource::GPIO, pin, false);
+                a.wait(0, WaitSource::GPIO, pin, false);
+            },
+
+            CaptureTrigger::Pattern { mask, value } => {
+                if mask == 0 {
+                    return Err(CaptureError::InvalidPattern);
+                }
+                let low = mask.trailing_zeros();
+                let width = 32 - mask.leading_zeros() - low;
+                if (mask >> low) != (u32::MAX >> (32 - width)) {
+                    return Err(CaptureError::InvalidPattern);
+                }
+                pattern = (value & mask) >> low;
+
+                let mut trig = a.label();
+                a.bind(&mut trig);
+                a.mov(MovDestination::OSR, MovOperation::None, MovSource::PINS);
+                if low > 0 {
+                    a.out(OutDestination::NULL, low as u8);
+                }
+                a.out(OutDestination::X, width as u8);
+                a.jmp(JmpCondition::XNotEqualY, &mut trig);
+            },
+        }
+
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        a.bind(&mut wrap_target);
+        a.r#in(InSource::PINS, 32);
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        self.slot
+            .start(pio, &program, |builder| {
+                builder
+                    .in_pin_base(0)
+                    // The pattern trigger drops the pins below the mask first
+                    .out_shift_direction(ShiftDirection::Right)
+                    .autopush(true)
+                    .push_threshold(32)
+                    .buffers(Buffers::RxTx)
+                    .clock_divisor(divisor)
+            })
+            .map_err(CaptureError::Pio)?;
+
+        // Start the dma before releasing the state machine
+        let dst = &mut self.buffer[0..samples as usize];
+        // Safety: read only access to the address of the rx fifo
+        let src = unsafe { &(*pac::PIO0::ptr()).rxf[CAPTURE_SM_INDEX] as *const _ as u32 };
+        dma_ctrl::start_to_memory(dma, dma_ctrl::CH_CAPTURE, src, dst, dma_ctrl::DREQ_PIO0_RX0 + CAPTURE_SM_INDEX as u8);
+
+        if let Some(task) = self.slot.task() {
+            task.tx.write(pattern);
+        }
+
+        self.pin_mask = pin_mask;
+        self.sample_rate = sample_rate;
+        self.samples = samples;
+        self.captured = 0;
+        self.state = CaptureState::Running;
+        Ok(())
+    }
+
+    /// Stop the capture, samples already captured are kept
+    pub fn stop(&mut self, pio: &mut PIO<PIO0>, dma: &pac::DMA) {
+        if self.state == CaptureState::Running {
+            dma_ctrl::abort(dma, dma_ctrl::CH_CAPTURE);
+            self.finish(pio, dma);
+        }
+    }
+
+    /// Release the resources at the end of the capture
+    fn finish(&mut self, pio: &mut PIO<PIO0>, dma: &pac::DMA) {
+        self.captured = self.samples - dma_ctrl::remaining(dma, dma_ctrl::CH_CAPTURE);
+        self.slot.stop(pio);
+        self.state = CaptureState::Done;
+    }
+
+    /// Detect the end of the capture
+    pub fn update(&mut self, pio: &mut PIO<PIO0>, dma: &pac::DMA) {
+        if self.state == CaptureState::Running && !dma_ctrl::is_busy(dma, dma_ctrl::CH_CAPTURE) {
+            self.finish(pio, dma);
+        }
+    }
+
+    /// Get the current state
+    pub fn state(&self) -> CaptureState {
+        self.state
+    }
+
+    /// Get the number of samples captured so far
+    pub fn captured(&self, dma: &pac::DMA) -> u32 {
+        match self.state {
+            CaptureState::Running => self.samples - dma_ctrl::remaining(dma, dma_ctrl::CH_CAPTURE),
+            _ => self.captured,
+        }
+    }
+
+    /// Get the pin mask of the capture
+    pub fn pin_mask(&self) -> u32 {
+        self.pin_mask
+    }
+
+    /// Get the sample rate of the capture
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of bytes used to send a sample
+    pub fn bytes_per_sample(&self) -> usize {
+        let span = 32 - self.pin_mask.leading_zeros() - self.pin_mask.trailing_zeros();
+        match span {
+            0..=8  => 1,
+            9..=16 => 2,
+            _      => 4,
+        }
+    }
+
+    /// Number of samples sent in a chunk
+    pub fn samples_per_chunk(&self) -> usize {
+        CHUNK_BYTES / self.bytes_per_sample()
+    }
+
+    /// Pack a chunk of samples into `dest`, returns the number of bytes used
+    pub fn read_chunk(&self, index: u32, dest: &mut [u8; CHUNK_BYTES]) -> Result<usize, CaptureError> {
+        if self.state != CaptureState::Done {
+            return Err(CaptureError::InvalidChunk);
+        }
+
+        let per_chunk = self.samples_per_chunk();
+        let first = (index as usize).checked_mul(per_chunk).ok_or(CaptureError::InvalidChunk)?;
+        if first >= self.captured as usize {
+            return Err(CaptureError::InvalidChunk);
+        }
+        let last = core::cmp::min(first + per_chunk, self.captured as usize);
+
+        let bytes = self.bytes_per_sample();
+        let low = self.pin_mask.trailing_zeros();
+        for (i, sample) in self.buffer[first..last].iter().enumerate() {
+            let packed = ((sample & self.pin_mask) >> low).to_le_bytes();
+            dest[i * bytes..(i + 1) * bytes].copy_from_slice(&packed[0..bytes]);
+        }
+
+        Ok((last - first) * bytes)
+    }
+}
+
+// ============================================================================
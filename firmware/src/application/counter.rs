@@ -0,0 +1,152 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+
+// ============================================================================
+
+/// Number of pwm slices
+const NB_SLICES: usize = 8;
+
+/// Edges counted by a pwm slice
+pub enum CounterEdge {
+    Rising,
+    Falling,
+}
+
+/// Errors of the counter operations
+pub enum CounterError {
+    /// Only the B input of a pwm slice (odd gpio) can be counted
+    NotAnInputB,
+
+    /// The pwm slice of the pin is already in use
+    SliceBusy,
+
+    /// No counter started on this pin
+    NotStarted,
+}
+
+/// State of a counter slice
+struct CounterSlice {
+    /// Counted pin
+    pin: u8,
+
+    /// Count accumulated from the previous hardware readings
+    total: u32,
+
+    /// Last value read from the 16 bits hardware counter
+    last: u16,
+}
+
+// ============================================================================
+
+/// Counts edges on pwm B inputs
+///
+/// The 16 bits hardware counters are extended to 32 bits in software, the
+/// `update` function must be called often enough to not miss a wrap.
+pub struct EdgeCounters {
+    /// One optional counter per pwm slice
+    slices: [Option<CounterSlice>; NB_SLICES],
+}
+
+// ============================================================================
+
+impl EdgeCounters {
+    ///
+    pub fn new() -> Self {
+        Self {
+            slices: [None, None, None, None, None, None, None, None],
+        }
+    }
+
+    /// Get the pwm slice of an odd gpio
+    fn slice_index(pin: u8) -> Result<usize, CounterError> {
+        match pin < 30 && pin % 2 == 1 {
+            true  => Ok(((pin >> 1) & 7) as usize),
+            false => Err(CounterError::NotAnInputB),
+        }
+    }
+
+    /// True if the pwm slice is used by a counter
+    pub fn is_slice_used(&self, slice: usize) -> bool {
+        self.slices[slice].is_some()
+    }
+
+    /// True if a counter runs on the pin
+    pub fn is_counting_on(&self, pin: u8) -> bool {
+        self.slices.iter().flatten().any(|s| s.pin == pin)
+    }
+
+    /// Start counting edges on the pin
+    ///
+    /// The pin must be set in pwm function mode by the caller
+    pub fn start(&mut self, pwm: &pac::PWM, pin: u8, edge: CounterEdge) -> Result<(), CounterError> {
+        let idx = Self::slice_index(pin)?;
+        if self.slices[idx].is_some() {
+            return Err(CounterError::SliceBusy);
+        }
+
+        let ch = &pwm.ch[idx];
+        ch.csr.write(|w| w.en().clear_bit());
+        ch.div.write(|w| unsafe { w.int().bits(1).frac().bits(0) });
+        ch.top.write(|w| unsafe { w.top().bits(0xFFFF) });
+        ch.ctr.write(|w| unsafe { w.ctr().bits(0) });
+        ch.csr.write(|w| {
+            match edge {
+                CounterEdge::Rising  => w.divmode().rise(),
+                CounterEdge::Falling => w.divmode().fall(),
+            };
+            w.en().set_bit()
+        });
+
+        self.slices[idx] = Some(CounterSlice {
+            pin: pin,
+            total: 0,
+            last: 0,
+        });
+        Ok(())
+    }
+
+    /// Stop the counter of the pin
+    pub fn stop(&mut self, pwm: &pac::PWM, pin: u8) -> Result<(), CounterError> {
+        let idx = self.started_slice(pin)?;
+        pwm.ch[idx].csr.write(|w| w.en().clear_bit());
+        self.slices[idx] = None;
+        Ok(())
+    }
+
+    /// Read the number of edges counted on the pin, optionally reset it
+    pub fn read(&mut self, pwm: &pac::PWM, pin: u8, reset: bool) -> Result<u32, CounterError> {
+        let idx = self.started_slice(pin)?;
+        self.update(pwm);
+
+        let slice = self.slices[idx].as_mut().unwrap();
+        let count = slice.total;
+        if reset {
+            slice.total = 0;
+        }
+        Ok(count)
+    }
+
+    /// Accumulate the hardware counters
+    pub fn update(&mut self, pwm: &pac::PWM) {
+        for (idx, slot) in self.slices.iter_mut().enumerate() {
+            if let Some(slice) = slot {
+                let raw = pwm.ch[idx].ctr.read().ctr().bits();
+                slice.total = slice.total.wrapping_add(raw.wrapping_sub(slice.last) as u32);
+                slice.last = raw;
+            }
+        }
+    }
+
+    /// Get the slice of a started counter
+    fn started_slice(&self, pin: u8) -> Result<usize, CounterError> {
+        let idx = Self::slice_index(pin)?;
+        match &self.slices[idx] {
+            Some(slice) if slice.pin == pin => Ok(idx),
+            _ => Err(CounterError::NotStarted),
+        }
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,104 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+
+// ============================================================================
+
+/// DMA channel used by the logic capture
+pub const CH_CAPTURE: usize = 0;
+
+/// DMA channel used by the pattern generator
+pub const CH_PATTERN: usize = 1;
+
+/// DMA channel restarting the pattern generator channel in loop mode
+pub const CH_PATTERN_RELOAD: usize = 2;
+
+/// Data request of the pio0 rx fifos
+pub const DREQ_PIO0_RX0: u8 = 4;
+
+/// Data request of the pio1 tx fifos
+pub const DREQ_PIO1_TX0: u8 = 8;
+
+/// Transfer as fast as possible
+const TREQ_PERMANENT: u8 = 0x3F;
+
+// ============================================================================
+
+/// Start a transfer from a peripheral fifo to memory
+pub fn start_to_memory(dma: &pac::DMA, ch: usize, src: u32, dst: &mut [u32], dreq: u8) {
+    start(dma, ch, src, false, dst.as_mut_ptr() as u32, true, dst.len() as u32, dreq, ch);
+}
+
+/// Start a transfer from memory to a peripheral fifo
+///
+/// At the end of the transfer, the channel `chain_to` is triggered (use `ch`
+/// to disable chaining)
+pub fn start_from_memory(dma: &pac::DMA, ch: usize, src: &[u32], dst: u32, dreq: u8, chain_to: usize) {
+    start(dma, ch, src.as_ptr() as u32, true, dst, false, src.len() as u32, dreq, chain_to);
+}
+
+/// Configure `ch` to restart `target` from the address stored in `addr`
+///
+/// `ch` is not started, it must be triggered by chaining from `target`.
+pub fn configure_reload(dma: &pac::DMA, ch: usize, target: usize, addr: &'static u32) {
+    let channel = &dma.ch[ch];
+    channel.ch_read_addr.write(|w| unsafe { w.bits(addr as *const u32 as u32) });
+    channel.ch_write_addr.write(|w| unsafe {
+        w.bits(&dma.ch[target].ch_al3_read_addr_trig as *const _ as u32)
+    });
+    channel.ch_trans_count.write(|w| unsafe { w.bits(1) });
+    channel.ch_al1_ctrl.write(|w| unsafe {
+        w.data_size().size_word()
+            .incr_read().clear_bit()
+            .incr_write().clear_bit()
+            .treq_sel().bits(TREQ_PERMANENT)
+            .chain_to().bits(ch as u8)
+            .en().set_bit()
+    });
+}
+
+/// Configure and trigger a channel
+fn start(
+    dma: &pac::DMA,
+    ch: usize,
+    src: u32,
+    incr_read: bool,
+    dst: u32,
+    incr_write: bool,
+    count: u32,
+    dreq: u8,
+    chain_to: usize,
+) {
+    let channel = &dma.ch[ch];
+    channel.ch_read_addr.write(|w| unsafe { w.bits(src) });
+    channel.ch_write_addr.write(|w| unsafe { w.bits(dst) });
+    channel.ch_trans_count.write(|w| unsafe { w.bits(count) });
+    channel.ch_ctrl_trig.write(|w| unsafe {
+        w.data_size().size_word()
+            .incr_read().bit(incr_read)
+            .incr_write().bit(incr_write)
+            .treq_sel().bits(dreq)
+            .chain_to().bits(chain_to as u8) // chaining to itself disables chaining
+            .en().set_bit()
+    });
+}
+
+/// Number of transfers not done yet
+pub fn remaining(dma: &pac::DMA, ch: usize) -> u32 {
+    dma.ch[ch].ch_trans_count.read().bits()
+}
+
+/// True while the channel transfers data
+pub fn is_busy(dma: &pac::DMA, ch: usize) -> bool {
+    dma.ch[ch].ch_ctrl_trig.read().busy().bit_is_set()
+}
+
+/// Abort the transfer of a channel
+pub fn abort(dma: &pac::DMA, ch: usize) {
+    dma.chan_abort.write(|w| unsafe { w.bits(1 << ch) });
+    while dma.chan_abort.read().bits() & (1 << ch) != 0 {}
+    dma.ch[ch].ch_ctrl_trig.modify(|_, w| w.en().clear_bit());
+}
+
+// ============================================================================
@@ -1,6 +1,11 @@
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use rp_pico::hal::gpio::{DYN_PULL_DOWN_INPUT, DYN_PULL_UP_INPUT, DYN_READABLE_OUTPUT};
 use rp_pico::hal::gpio::dynpin::DynPin;
+use rp_pico::hal::pac;
 use rp_pico::Pins;
 
+use picoha_io::processor::pins::{PinBackend, PinError, PinMode};
+
 /// Aliases the pins into DynPin
 pub struct GpioController {
     // TODO // implement declaration using a macro?
@@ -89,4 +94,55 @@ impl GpioController {
             _  => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// The plain io commands drive the pins through their DynPin
+impl PinBackend for GpioController {
+    fn is_valid(&self, idx: u8) -> bool {
+        // Same pins as borrow
+        matches!(idx, 0..=22 | 25)
+    }
+
+    fn set_mode(&mut self, idx: u8, mode: PinMode) -> Result<(), PinError> {
+        let io = self.borrow(idx).ok_or(PinError::InvalidPin)?;
+        let mode = match mode {
+            PinMode::PullUpInput   => DYN_PULL_UP_INPUT,
+            PinMode::PullDownInput => DYN_PULL_DOWN_INPUT,
+            PinMode::Output        => DYN_READABLE_OUTPUT,
+        };
+        io.try_into_mode(mode).map_err(|_| PinError::InvalidMode)
+    }
+
+    fn write(&mut self, idx: u8, level: bool) -> Result<(), PinError> {
+        let io = self.borrow(idx).ok_or(PinError::InvalidPin)?;
+        match level {
+            true  => io.set_high(),
+            false => io.set_low(),
+        }.map_err(|_| PinError::InvalidMode)
+    }
+
+    fn read(&mut self, idx: u8) -> Result<bool, PinError> {
+        let io = self.borrow(idx).ok_or(PinError::InvalidPin)?;
+        io.is_high().map_err(|_| PinError::InvalidMode)
+    }
+}
+
+/// Invert (or not) the output signal of the pin, after the peripheral mux
+pub fn set_output_inverted(idx: u8, inverted: bool) {
+    // Safety: only the output override field of this pin is modified
+    let io_bank0 = unsafe { &*pac::IO_BANK0::ptr() };
+    io_bank0.gpio[idx as usize].gpio_ctrl.modify(|_, w| match inverted {
+        true  => w.outover().invert(),
+        false => w.outover().normal(),
+    });
+}
+
+/// Invert (or not) the input signal of the pin, before the peripheral mux
+pub fn set_input_inverted(idx: u8, inverted: bool) {
+    // Safety: only the input override field of this pin is modified
+    let io_bank0 = unsafe { &*pac::IO_BANK0::ptr() };
+    io_bank0.gpio[idx as usize].gpio_ctrl.modify(|_, w| match inverted {
+        true  => w.inover().invert(),
+        false => w.inover().normal(),
+    });
+}
@@ -0,0 +1,259 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+
+// Algos
+use heapless::spsc::Queue;
+
+// ============================================================================
+
+/// Size of the emulated register map
+pub const I2C_TARGET_MAP_SIZE: usize = 256;
+
+/// Max number of events recorded between two fetches
+const EVENT_QUEUE_SIZE: usize = 256;
+
+/// Event kinds, first byte of each event record
+const EVT_POINTER: u8 = 1;
+const EVT_WRITE: u8 = 2;
+const EVT_STOP: u8 = 3;
+
+/// Size of an event record
+pub const EVENT_BYTES: usize = 4;
+
+/// Errors of the i2c target operations
+pub enum I2cTargetError {
+    /// The pin is not the SDA pin of an i2c block
+    InvalidPin,
+
+    /// Invalid 7 bits address
+    InvalidAddress,
+
+    /// The target is already started
+    Busy,
+
+    /// The data does not fit in the register map
+    OutOfMap,
+}
+
+/// Configuration of the running target
+struct TargetBus {
+    /// Index of the i2c block
+    block: u8,
+
+    /// SDA pin, SCL is the next one
+    sda: u8,
+}
+
+// ============================================================================
+
+/// Emulates an i2c device with a 256 bytes register map
+///
+/// The first byte written by the controller after the address sets the
+/// register pointer, the next ones are stored in the map. Reads return the
+/// map from the pointer. The pointer auto increments and wraps.
+///
+/// The bus is polled by `update`, the peripheral stretches the clock while a
+/// read request or a full rx fifo waits for the cpu.
+pub struct I2cTarget {
+    /// First i2c block
+    i2c0: pac::I2C0,
+
+    /// Second i2c block
+    i2c1: pac::I2C1,
+
+    /// Running configuration
+    bus: Option<TargetBus>,
+
+    /// Register map
+    map: [u8; I2C_TARGET_MAP_SIZE],
+
+    /// Register pointer
+    pointer: u8,
+
+    /// Events recorded for the host, 4 bytes packed little endian
+    events: Queue<u32, EVENT_QUEUE_SIZE>,
+
+    /// True if events have been dropped since the last fetch
+    overflow: bool,
+}
+
+// ============================================================================
+
+impl I2cTarget {
+    ///
+    pub fn new(i2c0: pac::I2C0, i2c1: pac::I2C1) -> Self {
+        Self {
+            i2c0: i2c0,
+            i2c1: i2c1,
+            bus: None,
+            map: [0; I2C_TARGET_MAP_SIZE],
+            pointer: 0,
+            events: Queue::new(),
+            overflow: false,
+        }
+    }
+
+    /// Get the i2c block of a SDA pin
+    ///
+    /// i2c0 SDA is on gpio 0, 4, 8... and i2c1 SDA on gpio 2, 6, 10...
+    pub fn sda_block(pin: u8) -> Result<u8, I2cTargetError> {
+        match pin < 22 && pin % 2 == 0 {
+            true  => Ok((pin >> 1) & 1),
+            false => Err(I2cTargetError::InvalidPin),
+        }
+    }
+
+    /// Registers of the i2c block
+    fn regs(&self, block: u8) -> &pac::i2c0::RegisterBlock {
+        match block {
+            0 => &self.i2c0,
+            _ => &self.i2c1,
+        }
+    }
+
+    /// Start answering to the address on the SDA pin and the next one (SCL)
+    ///
+    /// The pins must be set in i2c function mode by the caller
+    pub fn start(&mut self, sda: u8, address: u8) -> Result<(), I2cTargetError> {
+        if self.bus.is_some() {
+            return Err(I2cTargetError::Busy);
+        }
+        let block = Self::sda_block(sda)?;
+        if address < 0x08 || address > 0x77 {
+            return Err(I2cTargetError::InvalidAddress);
+        }
+
+        let i2c = self.regs(block);
+        i2c.ic_enable.write(|w| w.enable().clear_bit());
+        i2c.ic_con.write(|w| {
+            w.master_mode().clear_bit()
+                .ic_slave_disable().clear_bit()
+                .speed().fast()
+                .stop_det_ifaddressed().set_bit()
+                .rx_fifo_full_hld_ctrl().set_bit()
+        });
+        i2c.ic_sar.write(|w| unsafe { w.ic_sar().bits(address as u16) });
+        i2c.ic_rx_tl.write(|w| unsafe { w.rx_tl().bits(0) });
+        i2c.ic_tx_tl.write(|w| unsafe { w.tx_tl().bits(0) });
+        i2c.ic_enable.write(|w| w.enable().set_bit());
+
+        self.pointer = 0;
+        while self.events.dequeue().is_some() {}
+        self.overflow = false;
+        self.bus = Some(TargetBus {
+            block: block,
+            sda: sda,
+        });
+        Ok(())
+    }
+
+    /// Stop the target, returns the SDA pin to release
+    pub fn stop(&mut self) -> Option<u8> {
+        let bus = self.bus.take()?;
+        self.regs(bus.block).ic_enable.write(|w| w.enable().clear_bit());
+        Some(bus.sda)
+    }
+
+    /// Load bytes in the register map
+    pub fn load(&mut self, offset: usize, data: &[u8]) -> Result<(), I2cTargetError> {
+        let end = offset.checked_add(data.len()).ok_or(I2cTargetError::OutOfMap)?;
+        match self.map.get_mut(offset..end) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                Ok(())
+            },
+            None => Err(I2cTargetError::OutOfMap),
+        }
+    }
+
+    /// Read bytes of the register map
+    pub fn dump(&self, offset: usize, size: usize) -> Result<&[u8], I2cTargetError> {
+        let end = offset.checked_add(size).ok_or(I2cTargetError::OutOfMap)?;
+        self.map.get(offset..end).ok_or(I2cTargetError::OutOfMap)
+    }
+
+    /// Move the recorded events into `dest`, 4 bytes per event
+    ///
+    /// Returns the number of events and whether events have been dropped
+    pub fn fetch_events(&mut self, dest: &mut [u8]) -> (usize, bool) {
+        let mut count = 0;
+        for chunk in dest.chunks_exact_mut(EVENT_BYTES) {
+            match self.events.dequeue() {
+                Some(evt) => chunk.copy_from_slice(&evt.to_le_bytes()),
+                None => break,
+            }
+            count += 1;
+        }
+
+        let overflow = self.overflow;
+        self.overflow = false;
+        (count, overflow)
+    }
+
+    /// True if the target answers on the bus
+    pub fn is_active(&self) -> bool {
+        self.bus.is_some()
+    }
+
+    /// Serve the controller requests
+    pub fn update(&mut self) {
+        let block = match &self.bus {
+            Some(bus) => bus.block,
+            None => return,
+        };
+
+        // Safety: the i2c blocks are owned by self, the registers are only
+        // accessed from here while the target runs
+        let i2c = unsafe {
+            match block {
+                0 => &*pac::I2C0::ptr(),
+                _ => &*pac::I2C1::ptr(),
+            }
+        };
+        let raw = i2c.ic_raw_intr_stat.read();
+
+        // Bytes written by the controller
+        while i2c.ic_rxflr.read().rxflr().bits() > 0 {
+            let data = i2c.ic_data_cmd.read();
+            let byte = data.dat().bits();
+            if data.first_data_byte().bit_is_set() {
+                self.pointer = byte;
+                self.record(EVT_POINTER, byte, 0);
+            } else {
+                self.map[self.pointer as usize] = byte;
+                self.record(EVT_WRITE, self.pointer, byte);
+                self.pointer = self.pointer.wrapping_add(1);
+            }
+        }
+
+        // Byte requested by the controller
+        if raw.rd_req().bit_is_set() {
+            i2c.ic_clr_rd_req.read();
+            let byte = self.map[self.pointer as usize];
+            i2c.ic_data_cmd.write(|w| unsafe { w.dat().bits(byte) });
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+
+        // The controller nacked before the end of the tx fifo
+        if raw.tx_abrt().bit_is_set() {
+            i2c.ic_clr_tx_abrt.read();
+        }
+
+        if raw.stop_det().bit_is_set() {
+            i2c.ic_clr_stop_det.read();
+            self.record(EVT_STOP, self.pointer, 0);
+        }
+    }
+
+    /// Record an event for the host
+    fn record(&mut self, kind: u8, register: u8, value: u8) {
+        let evt = u32::from_le_bytes([kind, register, value, 0]);
+        if self.events.enqueue(evt).is_err() {
+            self.overflow = true;
+        }
+    }
+}
+
+// ============================================================================
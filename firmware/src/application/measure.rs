@@ -0,0 +1,158 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal;
+use rp_pico::hal::pac::PIO0;
+use rp_pico::hal::pio::{Buffers, PIO, SM1};
+
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Cycles spent outside the high counting loop, for each period
+const HIGH_OVERHEAD_CYCLES: u64 = 4;
+
+/// Cycles spent outside the low counting loop, for each period
+const LOW_OVERHEAD_CYCLES: u64 = 5;
+
+/// Samples with this bit set are high widths, the others are low widths
+const HIGH_SAMPLE_FLAG: u32 = 0x8000_0000;
+
+// ============================================================================
+
+/// Result of a signal measurement, durations in system clock cycles
+pub struct Measure {
+    /// Mean high pulse width
+    pub high_cycles: u64,
+
+    /// Mean low pulse width
+    pub low_cycles: u64,
+
+    /// Number of samples used
+    pub samples: u32,
+}
+
+/// Errors of the measurement
+pub enum MeasureError {
+    /// Cannot start the state machine
+    Pio(PioError),
+
+    /// No edge detected before the timeout
+    NoSignal,
+}
+
+// ============================================================================
+
+/// Measures the pulse widths of a signal with a pio state machine
+///
+/// The state machine counts the high and low durations at the system clock
+/// and pushes one sample per level change.
+pub struct SignalMeter {
+    /// State machine used to count cycles
+    slot: PioSlot<PIO0, SM1>,
+}
+
+// ============================================================================
+
+impl SignalMeter {
+    ///
+    pub fn new(slot: PioSlot<PIO0, SM1>) -> Self {
+        Self {
+            slot: slot,
+        }
+    }
+
+    /// Measure the signal on the pin during `gate_us`
+    ///
+    /// Fails if no complete period is seen before `timeout_us`
+    pub fn measure(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        timer: &hal::Timer,
+        pin: u8,
+        gate_us: u32,
+        timeout_us: u32,
+    ) -> Result<Measure, MeasureError> {
+        let program = pio_proc::pio_asm!(
+            "wait 0 pin 0",
+            "wait 1 pin 0",      // synchronize on a rising edge
+            ".wrap_target",
+            "    mov x, ~null",
+            "high:",
+            "    jmp pin high_dec",
+            "    jmp high_end",
+            "high_dec:",
+            "    jmp x-- high",
+            "high_end:",
+            "    mov isr, x",     // high sample, msb set
+            "    push noblock",
+            "    mov x, ~null",
+            "low:",
+            "    jmp pin low_end",
+            "    jmp x-- low",
+            "low_end:",
+            "    mov isr, ~x",    // low sample, msb cleared
+            "    push noblock",
+            ".wrap",
+        );
+
+        self.slot
+            .start(pio, &program.program, |builder| {
+                builder
+                    .in_pin_base(pin)
+                    .jmp_pin(pin)
+                    .buffers(Buffers::OnlyRx)
+                    .clock_divisor(1.0)
+            })
+            .map_err(MeasureError::Pio)?;
+
+        let mut high_sum: u64 = 0;
+        let mut high_count: u32 = 0;
+        let mut low_sum: u64 = 0;
+        let mut low_count: u32 = 0;
+
+        let start = timer.get_counter();
+        let mut gate_start: Option<u64> = None;
+        if let Some(task) = self.slot.task() {
+            loop {
+                let now = timer.get_counter();
+
+                // Stop at the end of the gate or when no signal is detected
+                match gate_start {
+                    Some(t) if now - t >= gate_us as u64 => break,
+                    None if now - start >= timeout_us as u64 => break,
+                    _ => {}
+                }
+
+                if let Some(sample) = task.rx.read() {
+                    if sample & HIGH_SAMPLE_FLAG != 0 {
+                        high_sum += (!sample) as u64;
+                        high_count += 1;
+                    } else {
+                        low_sum += sample as u64;
+                        low_count += 1;
+                    }
+
+                    // The gate starts with the first complete period
+                    if gate_start.is_none() && high_count > 0 && low_count > 0 {
+                        gate_start = Some(now);
+                    }
+                }
+            }
+        }
+
+        self.slot.stop(pio);
+
+        if high_count == 0 || low_count == 0 {
+            return Err(MeasureError::NoSignal);
+        }
+
+        Ok(Measure {
+            high_cycles: 2 * high_sum / high_count as u64 + HIGH_OVERHEAD_CYCLES,
+            low_cycles: 2 * low_sum / low_count as u64 + LOW_OVERHEAD_CYCLES,
+            samples: high_count + low_count,
+        })
+    }
+}
+
+// ============================================================================
@@ -4,40 +4,136 @@
 use embedded_hal::digital::v2::OutputPin;
 
 use rp_pico::hal;
+use rp_pico::hal::pac;
 use rp_pico::hal::gpio::{DYN_PULL_DOWN_INPUT, DYN_PULL_UP_INPUT, DYN_READABLE_OUTPUT, DynPinMode};
-use rp_pico::hal::gpio::dynpin::DynPin;
-
-use embedded_hal::digital::v2::InputPin;
+use rp_pico::hal::gpio::dynpin::DynFunction;
+use rp_pico::hal::pio::{PIOExt, PIO};
 
 // Algos
+use heapless::String;
 use core::str::FromStr;
 use core::write;
 use core::fmt::Write;
 
 
-// Protocol
-mod protocol;
-use protocol::{Answer, AnswerText, Command, CommandCode};
-use protocol::{CmdPinDirValue, CmdPinWriteValue};
+// Protocol and command processing
+use picoha_io::processor::{CommandProcessor, ProcessorState};
+use picoha_io::processor::protocol;
+use picoha_io::processor::protocol::{Answer, AnswerStatus, AnswerText, Command, CommandCode};
+use picoha_io::processor::protocol::{AnswerData, DataAnswer, InfoAnswer};
+use picoha_io::processor::protocol::{CmdPinWriteValue, CmdPulsePolarity};
+use picoha_io::processor::protocol::{CmdCounterEdgeValue, CmdCaptureTriggerValue, CmdPatternTimingValue};
+use picoha_io::processor::protocol::{CmdLedFormatValue, CmdServoTargetValue};
+use picoha_io::processor::protocol::{CmdStepperMoveValue, CmdStepperStopValue};
+use picoha_io::processor::protocol::{CmdIdentityValue, CmdRebootValue, CmdSafeStateValue};
+#[cfg(feature = "vendor")]
+use picoha_io::processor::protocol::{FrameKind, Response};
+#[cfg(feature = "vendor")]
+use picoha_io::processor::frame::FrameError;
+use picoha_io::processor::scpi::ScpiReply;
 
 // GPIO Control
 mod gpio_ctrl;
 use gpio_ctrl::GpioController;
 
+// PIO programs
+mod pio_slot;
+use pio_slot::{PioError, PioSlot};
+
+mod pulse;
+use pulse::PulseGenerator;
+
+mod measure;
+use measure::{MeasureError, SignalMeter};
+
+mod quadrature;
+use quadrature::QuadratureDecoder;
+
+mod capture;
+use capture::{CaptureError, CaptureState, CaptureTrigger, LogicCapture};
+
+mod pattern;
+use pattern::{PatternError, PatternGenerator, PatternTiming};
+
+mod ws2812;
+use ws2812::{LedFormat, Ws2812Driver};
+
+mod stepper;
+use stepper::{StepperDriver, StepperError, StepperState};
+
+mod onewire;
+use onewire::{OneWireBus, OneWireError, OneWireMaster, RomCode};
+
+// DMA
+mod dma_ctrl;
+
+// I2C features
+mod i2c_target;
+use i2c_target::{I2cTarget, I2cTargetError};
+
+// SPI features
+mod spi_target;
+use spi_target::{SpiTarget, SpiTargetError};
+
+// PWM features
+mod counter;
+use counter::{CounterEdge, CounterError, EdgeCounters};
+
+mod servo;
+use servo::{ServoError, ServoOutputs};
+
+// Device identity
+use crate::platform::{DeviceIdentity, IdentityError, USB_MANUFACTURER_NAME};
+
 // ============================================================================
 
-enum CmdError {
-    /// Arg value is invalid
-    ArgError(u8),
+/// Reboot requested by the host, done once the answer is sent
+pub enum RebootRequest {
+    /// Restart the firmware through the watchdog
+    Soft,
 
-    /// A HAL error occured
-    HalError(hal::gpio::Error),
+    /// Restart in the rom usb bootloader (BOOTSEL mode)
+    Bootloader,
 }
 
 // ============================================================================
 
-mod buffer;
-use buffer::UsbBuffer;
+/// Default gate time of the measure command (in ms)
+const DEFAULT_GATE_TIME_MS: u32 = 100;
+
+/// Default timeout of the measure command (in ms)
+const DEFAULT_MEASURE_TIMEOUT_MS: u32 = 1000;
+
+/// Longest gate time and timeout of the measure command (in ms), the usb is
+/// not polled while it runs
+const MAX_MEASURE_TIME_MS: u32 = 1000;
+
+/// Default tick rate of the pattern steps (in Hz)
+const DEFAULT_PATTERN_RATE_HZ: u32 = 1_000_000;
+
+/// Longest pulse train (in us), the usb is not polled while it runs
+const MAX_PULSE_TRAIN_US: u64 = 500_000;
+
+/// Default frequency of the servo signal (in Hz)
+const DEFAULT_SERVO_FREQ_HZ: u32 = 50;
+
+/// Default calibration of the servos, pulse widths (in us) and angle range (in degrees)
+const DEFAULT_SERVO_MIN_US: u32 = 1000;
+const DEFAULT_SERVO_MAX_US: u32 = 2000;
+const DEFAULT_SERVO_RANGE_DEG: u32 = 180;
+
+/// Default dynamics of the stepper, in steps/s and steps/s^2
+const DEFAULT_STEPPER_SPEED: u32 = 1000;
+const DEFAULT_STEPPER_ACCEL: u32 = 2000;
+
+/// Default max travel of a stepper homing move (in steps)
+const DEFAULT_HOMING_MAX_STEPS: u32 = 1_000_000;
+
+/// Max number of devices listed by a 1-Wire search
+const ONEWIRE_MAX_DEVICES: usize = 32;
+
+/// Max number of raw bytes carried by a data answer, once in base64
+const DATA_ANSWER_MAX_BYTES: usize = protocol::MAX_DAT_SIZE / 4 * 3;
 
 // ============================================================================
 
@@ -46,11 +142,77 @@ pub struct PicohaIo {
     /// To manage delay
     delay: cortex_m::delay::Delay,
 
-    /// Buffer to hold incomnig data
-    usb_buffer: UsbBuffer<512>,
+    /// To measure elapsed time (1 MHz)
+    timer: hal::Timer,
+
+    /// Incoming lines and protocol state
+    commands: ProcessorState,
 
     /// Controls gpios
     gpio_ctrl: GpioController,
+
+    /// Number of system clock cycles per microsecond
+    cycles_per_us: u32,
+
+    /// First pio block
+    pio0: PIO<pac::PIO0>,
+
+    /// Pulse trains generator
+    pulse: PulseGenerator,
+
+    /// Frequency and pulse width meter
+    meter: SignalMeter,
+
+    /// Logic analyzer
+    capture: LogicCapture,
+
+    /// Addressable leds driver
+    ws2812: Ws2812Driver,
+
+    /// Second pio block
+    pio1: PIO<pac::PIO1>,
+
+    /// Quadrature encoder decoder
+    quadrature: QuadratureDecoder,
+
+    /// Pattern generator
+    pattern: PatternGenerator,
+
+    /// Stepper motor driver
+    stepper: StepperDriver,
+
+    /// Limit input of the homing move in progress
+    limit_pin: Option<u8>,
+
+    /// 1-Wire bus master
+    onewire: OneWireMaster,
+
+    /// PWM slices
+    pwm: pac::PWM,
+
+    /// Edge counters on pwm B inputs
+    counters: EdgeCounters,
+
+    /// Servo outputs on pwm slices
+    servos: ServoOutputs,
+
+    /// DMA channels
+    dma: pac::DMA,
+
+    /// I2C device emulation
+    i2c_target: I2cTarget,
+
+    /// SPI device emulation
+    spi_target: SpiTarget,
+
+    /// Label and serial number of the device
+    identity: DeviceIdentity,
+
+    /// Reboot to do after the answer
+    reboot: Option<RebootRequest>,
+
+    /// When true, the safe state is applied on usb suspend and disconnection
+    safe_state: bool,
 }
 
 // ============================================================================
@@ -63,118 +225,701 @@ impl PicohaIo {
     /// Application intialization
     pub fn new(
         delay: cortex_m::delay::Delay,
+        timer: hal::Timer,
         pins: rp_pico::Pins,
+        sys_clk_hz: u32,
+        pio0: pac::PIO0,
+        pio1: pac::PIO1,
+        pwm: pac::PWM,
+        dma: pac::DMA,
+        i2c0: pac::I2C0,
+        i2c1: pac::I2C1,
+        spi0: pac::SPI0,
+        spi1: pac::SPI1,
+        resets: &mut pac::RESETS,
+        identity: DeviceIdentity,
     ) -> Self {
+        let (pio0, pio0_sm0, pio0_sm1, pio0_sm2, pio0_sm3) = pio0.split(resets);
+        let (pio1, pio1_sm0, pio1_sm1, pio1_sm2, pio1_sm3) = pio1.split(resets);
+
+        // Bring the pwm block out of reset
+        resets.reset.modify(|_, w| w.pwm().clear_bit());
+        while resets.reset_done.read().pwm().bit_is_clear() {}
+
+        // Bring the dma block out of reset
+        resets.reset.modify(|_, w| w.dma().clear_bit());
+        while resets.reset_done.read().dma().bit_is_clear() {}
+
+        // Bring the i2c blocks out of reset
+        resets.reset.modify(|_, w| w.i2c0().clear_bit().i2c1().clear_bit());
+        while resets.reset_done.read().i2c0().bit_is_clear() || resets.reset_done.read().i2c1().bit_is_clear() {}
+
         Self {
             delay:      delay,
-            usb_buffer: UsbBuffer::new(),
+            timer:      timer,
+            commands:   ProcessorState::new(),
             gpio_ctrl: GpioController::new(pins),
+            cycles_per_us: sys_clk_hz / 1_000_000,
+            pio0:       pio0,
+            pulse:      PulseGenerator::new(PioSlot::new(pio0_sm0)),
+            meter:      SignalMeter::new(PioSlot::new(pio0_sm1)),
+            capture:    LogicCapture::new(PioSlot::new(pio0_sm2)),
+            ws2812:     Ws2812Driver::new(PioSlot::new(pio0_sm3)),
+            pio1:       pio1,
+            quadrature: QuadratureDecoder::new(PioSlot::new(pio1_sm0)),
+            pattern:    PatternGenerator::new(PioSlot::new(pio1_sm1)),
+            stepper:    StepperDriver::new(PioSlot::new(pio1_sm2)),
+            limit_pin:  None,
+            onewire:    OneWireMaster::new(PioSlot::new(pio1_sm3)),
+            pwm:        pwm,
+            counters:   EdgeCounters::new(),
+            servos:     ServoOutputs::new(),
+            dma:        dma,
+            i2c_target: I2cTarget::new(i2c0, i2c1),
+            spi_target: SpiTarget::new(spi0, spi1),
+            identity:   identity,
+            reboot:     None,
+            safe_state: false,
         }
     }
 
-    // -----------------------------------------------------------------------
+    // ------------------------------------------------------------------------
+
+    /// To emit one or several pulses on an io
+    ///
+    /// arg is the polarity, val the pulse width in us, prm[0] the period in
+    /// us (twice the width by default) and prm[1] the number of pulses (1 by
+    /// default)
+    fn process_pulse(&mut self, cmd: &Command) -> Answer {
+        let active_low = match CmdPulsePolarity::from_u8(cmd.arg) {
+            Some(CmdPulsePolarity::ActiveHigh) => false,
+            Some(CmdPulsePolarity::ActiveLow)  => true,
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
 
-    /// Converts the mode argument to the hal mode constant
-    fn mode_arg_to_hal(mode: CmdPinDirValue) -> DynPinMode {
-        match mode {
-            CmdPinDirValue::PullUpInput    => DYN_PULL_UP_INPUT,
-            CmdPinDirValue::PullDownInput  => DYN_PULL_DOWN_INPUT,
-            CmdPinDirValue::ReadableOutput => DYN_READABLE_OUTPUT,
+        let width_us = match cmd.val {
+            Some(x) if x > 0 => x,
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing val").unwrap()),
+        };
+
+        let period_us = match cmd.prm_at(0) {
+            Some(x) if x as u32 > width_us => x as u32,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Period must be greater than width").unwrap()),
+            None    => width_us.saturating_mul(2),
+        };
+
+        let count = match cmd.prm_at(1) {
+            Some(x) if x > 0 => x as u32,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pulse count").unwrap()),
+            None    => 1,
+        };
+
+        if count as u64 * period_us as u64 > MAX_PULSE_TRAIN_US {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Pulse train too long").unwrap());
+        }
+
+        let (high_cycles, low_cycles) = match (
+            width_us.checked_mul(self.cycles_per_us),
+            (period_us - width_us).checked_mul(self.cycles_per_us),
+        ) {
+            (Some(h), Some(l)) if PulseGenerator::is_valid(h, l) => (h, l),
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Pulse timings out of range").unwrap()),
+        };
+
+        match self.gpio_ctrl.borrow(cmd.pin) {
+            Some(io) => {
+                // Drive the idle level, then give the pin to the pio
+                if io.try_into_mode(DYN_READABLE_OUTPUT).is_err() || io.set_low().is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot drive pin").unwrap());
+                }
+                gpio_ctrl::set_output_inverted(cmd.pin, active_low);
+                if io.try_into_mode(DynPinMode::Function(DynFunction::Pio0)).is_err() {
+                    gpio_ctrl::set_output_inverted(cmd.pin, false);
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to pio mode").unwrap());
+                }
+
+                let result = self.pulse.run(&mut self.pio0, cmd.pin, count, high_cycles, low_cycles);
+
+                // Give the pin back as an output at its idle level
+                io.try_into_mode(DYN_READABLE_OUTPUT).ok();
+                gpio_ctrl::set_output_inverted(cmd.pin, false);
+                match active_low {
+                    true  => io.set_high().ok(),
+                    false => io.set_low().ok(),
+                };
+
+                match result {
+                    Ok(())  => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("p").unwrap()).with_val(count),
+                    Err(e) => Self::answer_pio_error(cmd.pin, e),
+                }
+            },
+
+            None => Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Convert system clock cycles into nanoseconds, saturated to i32
+    fn cycles_to_ns(&self, cycles: u64) -> i32 {
+        let ns = cycles * 1000 / self.cycles_per_us as u64;
+        match ns > i32::MAX as u64 {
+            true  => i32::MAX,
+            false => ns as i32,
         }
     }
 
-    fn cmd_pin_set_io(io: &mut DynPin, mode: u8) -> Result<(), CmdError> {
-        match CmdPinDirValue::from_u8(mode) {
-            Some(x) => match io.try_into_mode(Self::mode_arg_to_hal(x)) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(CmdError::HalError(err)),
+    /// To measure the frequency and the pulse widths of an input signal
+    ///
+    /// val is the gate time in ms, prm[0] the timeout in ms. The answer val is
+    /// the frequency in Hz, prm holds [period ns, high ns, low ns, samples].
+    fn process_measure(&mut self, cmd: &Command) -> Answer {
+        if self.gpio_ctrl.borrow(cmd.pin).is_none() {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        let gate_ms = cmd.val.unwrap_or(DEFAULT_GATE_TIME_MS);
+        if gate_ms > MAX_MEASURE_TIME_MS {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Gate time too long").unwrap());
+        }
+        let timeout_ms = match cmd.prm_at(0) {
+            Some(x) if x > 0 && x as u32 <= MAX_MEASURE_TIME_MS => x as u32,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid timeout").unwrap()),
+            None    => DEFAULT_MEASURE_TIMEOUT_MS,
+        };
+
+        match self.meter.measure(
+            &mut self.pio0,
+            &self.timer,
+            cmd.pin,
+            gate_ms * 1000,
+            timeout_ms * 1000,
+        ) {
+            Ok(m) => {
+                let period_cycles = m.high_cycles + m.low_cycles;
+                let frequency = (self.cycles_per_us as u64 * 1_000_000) / period_cycles;
+
+                Answer::ok(cmd.pin, 0, AnswerText::from_str("f").unwrap())
+                    .with_val(frequency as u32)
+                    .with_prm(&[
+                        self.cycles_to_ns(period_cycles),
+                        self.cycles_to_ns(m.high_cycles),
+                        self.cycles_to_ns(m.low_cycles),
+                        m.samples as i32,
+                    ])
             },
-            None => Err(CmdError::ArgError(mode)),
+
+            Err(MeasureError::NoSignal) => Answer::error(cmd.pin, 0, AnswerText::from_str("No signal").unwrap()),
+            Err(MeasureError::Pio(e))   => Self::answer_pio_error(cmd.pin, e),
         }
     }
 
-    /// To configure the  mode of the io
+    // ------------------------------------------------------------------------
+
+    /// To start counting edges on an io
     ///
-    fn process_set_io_mode(&mut self, cmd: &Command) -> Answer {
-        // Get pin value
+    /// arg selects the counted edges (0 rising, 1 falling)
+    fn process_counter_start(&mut self, cmd: &Command) -> Answer {
+        let edge = match CmdCounterEdgeValue::from_u8(cmd.arg) {
+            Some(CmdCounterEdgeValue::Rising)  => CounterEdge::Rising,
+            Some(CmdCounterEdgeValue::Falling) => CounterEdge::Falling,
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+
+        if self.servos.is_slice_used(Self::pwm_slice(cmd.pin)) {
+            return Self::answer_counter_error(cmd.pin, CounterError::SliceBusy);
+        }
+
         match self.gpio_ctrl.borrow(cmd.pin) {
-            Some(io) => match Self::cmd_pin_set_io(io, cmd.arg) {
-                Ok(_) => Answer::ok(
-                    cmd.pin,
-                    0,
-                    AnswerText::from_str("m").unwrap()
-                ),
-
-                Err(err) => match err {
-                    CmdError::HalError(_) => Answer::error(
-                        0,
-                        0,
-                        AnswerText::from_str("Cannot set desired I/O mode").unwrap(),
-                    ),
-
-                    CmdError::ArgError(x) => {
-                        let mut txt = AnswerText::new();
-                        write!(txt, "Invalid arg: {}", x).unwrap();
-
-                        Answer::error(
-                            0,
-                            0,
-                            txt
-                        )
+            Some(io) => match self.counters.start(&self.pwm, cmd.pin, edge) {
+                Ok(()) => match io.try_into_mode(DynPinMode::Function(DynFunction::Pwm)) {
+                    Ok(_)  => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("c").unwrap()),
+                    Err(_) => {
+                        self.counters.stop(&self.pwm, cmd.pin).ok();
+                        Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to pwm mode").unwrap())
                     },
-                }
+                },
+
+                Err(e) => Self::answer_counter_error(cmd.pin, e),
             },
 
             None => Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
         }
+    }
+
+    /// To read the edge counter of an io
+    ///
+    /// arg 1 resets the counter after the read
+    fn process_counter_read(&mut self, cmd: &Command) -> Answer {
+        let reset = match cmd.arg {
+            0 => false,
+            1 => true,
+            _ => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
 
+        match self.counters.read(&self.pwm, cmd.pin, reset) {
+            Ok(count) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("c").unwrap()).with_val(count),
+            Err(e)    => Self::answer_counter_error(cmd.pin, e),
+        }
+    }
+
+    /// To stop the edge counter of an io, the pin goes back to input
+    fn process_counter_stop(&mut self, cmd: &Command) -> Answer {
+        match self.counters.stop(&self.pwm, cmd.pin) {
+            Ok(()) => {
+                if let Some(io) = self.gpio_ctrl.borrow(cmd.pin) {
+                    io.try_into_mode(DYN_PULL_DOWN_INPUT).ok();
+                }
+                Answer::ok(cmd.pin, 0, AnswerText::from_str("c").unwrap())
+            },
+
+            Err(e) => Self::answer_counter_error(cmd.pin, e),
+        }
+    }
+
+    /// Build the error answer of a counter failure
+    fn answer_counter_error(pin: u8, err: CounterError) -> Answer {
+        match err {
+            CounterError::NotAnInputB => Answer::error(pin, 0, AnswerText::from_str("Counters are only available on odd pins").unwrap()),
+            CounterError::SliceBusy   => Answer::error(pin, 0, AnswerText::from_str("PWM slice busy").unwrap()),
+            CounterError::NotStarted  => Answer::error(pin, 0, AnswerText::from_str("Counter not started").unwrap()),
+        }
+    }
+
+    /// Get the pwm slice of a pin
+    fn pwm_slice(pin: u8) -> usize {
+        ((pin >> 1) & 7) as usize
     }
 
     // ------------------------------------------------------------------------
 
-    fn cmd_pin_set_value(io: &mut DynPin, value: u8) -> Result<(), CmdError> {
-        // Parse argument
-        match CmdPinWriteValue::from_u8(value) {
-            Some(x) => {
-                match x {
-                    CmdPinWriteValue::High => match io.set_high() {
-                        Ok(_) => Ok(()),
-                        Err(e) => Err(CmdError::HalError(e)),
+    /// To start a servo signal on an io, at the middle of its limits
+    ///
+    /// val is the frequency (50 Hz by default) and prm holds the calibration
+    /// [min width in us, max width in us, angle at max width in degrees]
+    fn process_servo_start(&mut self, cmd: &Command) -> Answer {
+        if self.counters.is_slice_used(Self::pwm_slice(cmd.pin)) {
+            return Self::answer_servo_error(cmd.pin, ServoError::SliceBusy);
+        }
+
+        let freq_hz   = cmd.val.unwrap_or(DEFAULT_SERVO_FREQ_HZ);
+        let min_us    = cmd.prm_at(0).map(|x| x as u32).unwrap_or(DEFAULT_SERVO_MIN_US);
+        let max_us    = cmd.prm_at(1).map(|x| x as u32).unwrap_or(DEFAULT_SERVO_MAX_US);
+        let range_deg = cmd.prm_at(2).map(|x| x as u32).unwrap_or(DEFAULT_SERVO_RANGE_DEG);
+
+        let sys_clk_hz = self.cycles_per_us * 1_000_000;
+        match self.gpio_ctrl.borrow(cmd.pin) {
+            Some(io) => match self.servos.start(&self.pwm, sys_clk_hz, cmd.pin, freq_hz, min_us, max_us, range_deg) {
+                Ok(()) => match io.try_into_mode(DynPinMode::Function(DynFunction::Pwm)) {
+                    Ok(_)  => Answer::ok(cmd.pin, 0, AnswerText::from_str("s").unwrap()).with_val((min_us + max_us) / 2),
+                    Err(_) => {
+                        self.servos.stop(&self.pwm, cmd.pin).ok();
+                        Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to pwm mode").unwrap())
                     },
+                },
 
-                    CmdPinWriteValue::Low => match io.set_low() {
-                        Ok(_) => Ok(()),
-                        Err(e) => Err(CmdError::HalError(e)),
-                    }
+                Err(e) => Self::answer_servo_error(cmd.pin, e),
+            },
+
+            None => Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+        }
+    }
+
+    /// To move a servo
+    ///
+    /// arg selects the target in val (0 width in us, 1 angle in degrees),
+    /// prm[0] is the optional ramp duration in ms
+    fn process_servo_set(&mut self, cmd: &Command) -> Answer {
+        let target = match cmd.val {
+            Some(x) => x,
+            None    => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing val").unwrap()),
+        };
+
+        let width_us = match CmdServoTargetValue::from_u8(cmd.arg) {
+            Some(CmdServoTargetValue::Width) => Ok(target),
+            Some(CmdServoTargetValue::Angle) => self.servos.angle_to_width(cmd.pin, target),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+
+        let ramp_us = match cmd.prm_at(0) {
+            Some(x) if x >= 0 => (x as u32).saturating_mul(1000),
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid ramp duration").unwrap()),
+            None    => 0,
+        };
+
+        let now = self.timer.get_counter();
+        match width_us.and_then(|w| self.servos.set_width(&self.pwm, cmd.pin, w, now, ramp_us).map(|_| w)) {
+            Ok(w)  => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("s").unwrap()).with_val(w),
+            Err(e) => Self::answer_servo_error(cmd.pin, e),
+        }
+    }
+
+    /// To read the current width of a servo, the answer arg is 1 while moving
+    fn process_servo_read(&mut self, cmd: &Command) -> Answer {
+        match self.servos.read(cmd.pin) {
+            Ok((width_us, moving)) => Answer::ok(cmd.pin, moving as u8, AnswerText::from_str("s").unwrap()).with_val(width_us),
+            Err(e) => Self::answer_servo_error(cmd.pin, e),
+        }
+    }
+
+    /// To stop the servo signal of an io, the pin goes back to a low output
+    fn process_servo_stop(&mut self, cmd: &Command) -> Answer {
+        match self.servos.stop(&self.pwm, cmd.pin) {
+            Ok(()) => {
+                if let Some(io) = self.gpio_ctrl.borrow(cmd.pin) {
+                    io.try_into_mode(DYN_READABLE_OUTPUT).ok();
+                    io.set_low().ok();
                 }
+                Answer::ok(cmd.pin, 0, AnswerText::from_str("s").unwrap())
+            },
+
+            Err(e) => Self::answer_servo_error(cmd.pin, e),
+        }
+    }
+
+    /// Build the error answer of a servo failure
+    fn answer_servo_error(pin: u8, err: ServoError) -> Answer {
+        match err {
+            ServoError::SliceBusy        => Answer::error(pin, 0, AnswerText::from_str("PWM slice busy").unwrap()),
+            ServoError::NotStarted       => Answer::error(pin, 0, AnswerText::from_str("Servo not started").unwrap()),
+            ServoError::InvalidFrequency => Answer::error(pin, 0, AnswerText::from_str("Invalid frequency").unwrap()),
+            ServoError::InvalidLimits    => Answer::error(pin, 0, AnswerText::from_str("Invalid servo limits").unwrap()),
+            ServoError::OutOfRange       => Answer::error(pin, 0, AnswerText::from_str("Target out of servo limits").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To start the quadrature decoder
+    ///
+    /// Phase A is on pin, phase B on pin + 1. Both are set as pull-up inputs.
+    fn process_quad_start(&mut self, cmd: &Command) -> Answer {
+        for pin in [cmd.pin, cmd.pin.wrapping_add(1)] {
+            match self.gpio_ctrl.borrow(pin) {
+                Some(io) => {
+                    if io.try_into_mode(DYN_PULL_UP_INPUT).is_err() {
+                        return Answer::error(pin, 0, AnswerText::from_str("Cannot set desired I/O mode").unwrap());
+                    }
+                },
+                None => return Answer::error(pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
             }
+        }
 
-            None => Err(CmdError::ArgError(value)),
+        match self.quadrature.start(&mut self.pio1, &self.timer, cmd.pin) {
+            Ok(())  => Answer::ok(cmd.pin, 0, AnswerText::from_str("q").unwrap()),
+            Err(e) => Self::answer_pio_error(cmd.pin, e),
         }
     }
 
-    /// To write a value on the io
-    fn process_write_io(&mut self, cmd: &Command) -> Answer {
-        match self.gpio_ctrl.borrow(cmd.pin) {
-            Some(io) => match Self::cmd_pin_set_value(io, cmd.arg) {
-                Ok(())             => Answer::ok(cmd.pin, 0, AnswerText::from_str("m").unwrap()),
-                Err(err) => match err {
-                    CmdError::HalError(_) => Answer::error(
-                        cmd.pin,
-                        0,
-                        AnswerText::from_str("Cannot set desired pin value. Is direction correct?").unwrap(),
-                    ),
-
-                    CmdError::ArgError(x) => {
-                        let mut txt = AnswerText::new();
-                        write!(txt, "Invalid arg: {}", x).unwrap();
-
-                        Answer::error(
-                            cmd.pin,
-                            0,
-                            txt
-                        )
+    /// To read the quadrature decoder
+    ///
+    /// The answer prm holds [position, velocity in counts/s]
+    fn process_quad_read(&mut self, cmd: &Command) -> Answer {
+        if !self.quadrature.is_running_on(cmd.pin) {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Decoder not started").unwrap());
+        }
+
+        match self.quadrature.position() {
+            Some(position) => Answer::ok(cmd.pin, 0, AnswerText::from_str("q").unwrap())
+                .with_prm(&[position, self.quadrature.velocity()]),
+            None => Answer::error(cmd.pin, 0, AnswerText::from_str("Decoder not started").unwrap()),
+        }
+    }
+
+    /// To set the quadrature decoder position to prm[0] (0 by default)
+    fn process_quad_preset(&mut self, cmd: &Command) -> Answer {
+        let position = cmd.prm_at(0).unwrap_or(0);
+
+        match self.quadrature.is_running_on(cmd.pin) && self.quadrature.preset(position) {
+            true  => Answer::ok(cmd.pin, 0, AnswerText::from_str("q").unwrap()).with_prm(&[position]),
+            false => Answer::error(cmd.pin, 0, AnswerText::from_str("Decoder not started").unwrap()),
+        }
+    }
+
+    /// To stop the quadrature decoder
+    fn process_quad_stop(&mut self, cmd: &Command) -> Answer {
+        match self.quadrature.is_running_on(cmd.pin) {
+            true => {
+                self.quadrature.stop(&mut self.pio1);
+                Answer::ok(cmd.pin, 0, AnswerText::from_str("q").unwrap())
+            },
+            false => Answer::error(cmd.pin, 0, AnswerText::from_str("Decoder not started").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To start a logic capture
+    ///
+    /// arg is the trigger (0 immediate, 1 rising edge on pin, 2 falling edge
+    /// on pin, 3 pattern), val the pattern value and prm holds
+    /// [pin mask, sample rate in Hz, number of samples, pattern mask]
+    fn process_capture_start(&mut self, cmd: &Command) -> Answer {
+        let trigger = match CmdCaptureTriggerValue::from_u8(cmd.arg) {
+            Some(CmdCaptureTriggerValue::Immediate) => CaptureTrigger::Immediate,
+            Some(CmdCaptureTriggerValue::Rising)    => CaptureTrigger::Rising(cmd.pin),
+            Some(CmdCaptureTriggerValue::Falling)   => CaptureTrigger::Falling(cmd.pin),
+            Some(CmdCaptureTriggerValue::Pattern)   => CaptureTrigger::Pattern {
+                mask:  cmd.prm_at(3).unwrap_or(0) as u32,
+                value: cmd.val.unwrap_or(0),
+            },
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+
+        let (pin_mask, sample_rate, samples) = match (cmd.prm_at(0), cmd.prm_at(1), cmd.prm_at(2)) {
+            (Some(m), Some(r), Some(n)) => (m as u32, r as u32, n as u32),
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing prm").unwrap()),
+        };
+
+        match self.capture.start(
+            &mut self.pio0,
+            &self.dma,
+            self.cycles_per_us * 1_000_000,
+            pin_mask,
+            sample_rate,
+            samples,
+            trigger,
+        ) {
+            Ok(()) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("l").unwrap()).with_val(samples),
+            Err(e) => Self::answer_capture_error(cmd.pin, e),
+        }
+    }
+
+    /// To get the state of the logic capture
+    ///
+    /// The answer arg is the state (0 idle, 1 running, 2 done), val the
+    /// number of samples captured and prm holds
+    /// [pin mask, sample rate in Hz, bytes per sample, samples per chunk]
+    fn process_capture_status(&mut self, cmd: &Command) -> Answer {
+        self.capture.update(&mut self.pio0, &self.dma);
+
+        Answer::ok(cmd.pin, self.capture.state() as u8, AnswerText::from_str("l").unwrap())
+            .with_val(self.capture.captured(&self.dma))
+            .with_prm(&[
+                self.capture.pin_mask() as i32,
+                self.capture.sample_rate() as i32,
+                self.capture.bytes_per_sample() as i32,
+                self.capture.samples_per_chunk() as i32,
+            ])
+    }
+
+    /// To read the chunk val of the captured samples
+    fn process_capture_read(&mut self, cmd: &Command) -> DataAnswer {
+        let index = cmd.val.unwrap_or(0);
+        let mut raw = [0u8; capture::CHUNK_BYTES];
+        let mut encoded = [0u8; protocol::MAX_DAT_SIZE];
+
+        let mut answer = DataAnswer {
+            sts: AnswerStatus::Ok,
+            pin: cmd.pin,
+            arg: self.capture.bytes_per_sample() as u8,
+            msg: AnswerText::from_str("l").unwrap(),
+            val: index,
+            dat: AnswerData::new(),
+        };
+
+        match self.capture.read_chunk(index, &mut raw) {
+            Ok(size) => {
+                let len = base64::encode_config_slice(&raw[0..size], base64::STANDARD, &mut encoded);
+                // base64 output is always valid ascii
+                answer.dat.push_str(core::str::from_utf8(&encoded[0..len]).unwrap()).unwrap();
+            },
+
+            Err(_) => {
+                answer.sts = AnswerStatus::Error;
+                answer.msg = AnswerText::from_str("Invalid chunk").unwrap();
+            },
+        }
+
+        answer
+    }
+
+    /// To stop the logic capture, captured samples are kept
+    fn process_capture_stop(&mut self, cmd: &Command) -> Answer {
+        self.capture.stop(&mut self.pio0, &self.dma);
+        Answer::ok(cmd.pin, 0, AnswerText::from_str("l").unwrap()).with_val(self.capture.captured(&self.dma))
+    }
+
+    /// Build the error answer of a capture failure
+    fn answer_capture_error(pin: u8, err: CaptureError) -> Answer {
+        match err {
+            CaptureError::Pio(e)            => Self::answer_pio_error(pin, e),
+            CaptureError::InvalidRate       => Answer::error(pin, 0, AnswerText::from_str("Invalid sample rate").unwrap()),
+            CaptureError::InvalidCount      => Answer::error(pin, 0, AnswerText::from_str("Invalid sample count or pin mask").unwrap()),
+            CaptureError::InvalidPattern    => Answer::error(pin, 0, AnswerText::from_str("Pattern mask must cover contiguous pins").unwrap()),
+            CaptureError::InvalidTriggerPin => Answer::error(pin, 0, AnswerText::from_str("Invalid trigger pin").unwrap()),
+            CaptureError::InvalidChunk      => Answer::error(pin, 0, AnswerText::from_str("Invalid chunk").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Decode the base64 data of the command into `dest`
+    ///
+    /// Returns the number of decoded bytes
+    fn decode_cmd_data(cmd: &Command, dest: &mut [u8; protocol::MAX_CMD_DAT_SIZE]) -> Option<usize> {
+        match &cmd.dat {
+            Some(dat) => base64::decode_config_slice(dat.as_bytes(), base64::STANDARD, dest).ok(),
+            None => None,
+        }
+    }
+
+    /// To load pattern steps at the step offset val
+    ///
+    /// dat holds the steps in base64, 4 bytes little endian per step
+    fn process_pattern_load(&mut self, cmd: &Command) -> Answer {
+        let mut raw = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let size = match Self::decode_cmd_data(cmd, &mut raw) {
+            Some(size) => size,
+            None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing or invalid dat").unwrap()),
+        };
+
+        self.pattern_load(cmd.pin, cmd.val.unwrap_or(0) as usize, &raw[0..size])
+    }
+
+    /// Load raw pattern steps at the step offset
+    fn pattern_load(&mut self, pin: u8, offset: usize, raw: &[u8]) -> Answer {
+        match self.pattern.load(offset, raw) {
+            Ok(steps) => Answer::ok(pin, 0, AnswerText::from_str("g").unwrap()).with_val(steps as u32),
+            Err(e)    => Self::answer_pattern_error(pin, e),
+        }
+    }
+
+    /// To start the pattern playback on prm[0] pins from pin
+    ///
+    /// arg is the timing (0 step durations, 1 fixed rate), prm[1] the rate in
+    /// Hz (1 MHz by default) and val the number of plays (1 by default, 0 to
+    /// loop until stopped)
+    fn process_pattern_start(&mut self, cmd: &Command) -> Answer {
+        let rate = match cmd.prm_at(1) {
+            Some(x) if x > 0 => x as u32,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid rate").unwrap()),
+            None    => DEFAULT_PATTERN_RATE_HZ,
+        };
+        let timing = match CmdPatternTimingValue::from_u8(cmd.arg) {
+            Some(CmdPatternTimingValue::Durations) => PatternTiming::Durations(rate),
+            Some(CmdPatternTimingValue::FixedRate) => PatternTiming::FixedRate(rate),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+        let repeats = match cmd.val.unwrap_or(1) {
+            0 => None,
+            n => Some(n),
+        };
+        let count = match cmd.prm_at(0) {
+            Some(x) if x > 0 && x <= pattern::PATTERN_MAX_PINS as i32 => x as u8,
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin count").unwrap()),
+        };
+
+        // Give the pins to the pio
+        for pin in cmd.pin..cmd.pin.saturating_add(count) {
+            match self.gpio_ctrl.borrow(pin) {
+                Some(io) => {
+                    if io.try_into_mode(DynPinMode::Function(DynFunction::Pio1)).is_err() {
+                        return Answer::error(pin, 0, AnswerText::from_str("Cannot set pin to pio mode").unwrap());
                     }
+                },
+                None => return Answer::error(pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+            }
+        }
+
+        match self.pattern.start(
+            &mut self.pio1,
+            &self.dma,
+            self.cycles_per_us * 1_000_000,
+            cmd.pin,
+            count,
+            timing,
+            repeats,
+        ) {
+            Ok(()) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("g").unwrap()).with_val(self.pattern.steps() as u32),
+            Err(e) => {
+                self.release_pattern_pins(cmd.pin, count);
+                Self::answer_pattern_error(cmd.pin, e)
+            },
+        }
+    }
+
+    /// To stop the pattern playback, pins go back to low outputs
+    fn process_pattern_stop(&mut self, cmd: &Command) -> Answer {
+        if self.pattern.is_active() {
+            let (base, count) = self.pattern.pins();
+            self.pattern.stop(&mut self.pio1, &self.dma);
+            self.release_pattern_pins(base, count);
+        }
+        Answer::ok(cmd.pin, 0, AnswerText::from_str("g").unwrap())
+    }
+
+    /// To get the pattern playback state
+    ///
+    /// The answer arg is 1 while playing and val the remaining number of plays
+    /// (0 when looping)
+    fn process_pattern_status(&mut self, cmd: &Command) -> Answer {
+        let playing = self.pattern.is_playing(&self.dma);
+
+        Answer::ok(cmd.pin, playing as u8, AnswerText::from_str("g").unwrap())
+            .with_val(self.pattern.repeats().unwrap_or(0))
+    }
+
+    /// Give the pins back to the sio as low outputs
+    fn release_pattern_pins(&mut self, base: u8, count: u8) {
+        for pin in base..base.saturating_add(count) {
+            if let Some(io) = self.gpio_ctrl.borrow(pin) {
+                io.try_into_mode(DYN_READABLE_OUTPUT).ok();
+                io.set_low().ok();
+            }
+        }
+    }
+
+    /// Build the error answer of a pattern failure
+    fn answer_pattern_error(pin: u8, err: PatternError) -> Answer {
+        match err {
+            PatternError::Pio(e)       => Self::answer_pio_error(pin, e),
+            PatternError::InvalidRate  => Answer::error(pin, 0, AnswerText::from_str("Invalid rate").unwrap()),
+            PatternError::InvalidSteps => Answer::error(pin, 0, AnswerText::from_str("Invalid steps").unwrap()),
+            PatternError::InvalidPins  => Answer::error(pin, 0, AnswerText::from_str("Invalid pin count").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To write the colors of dat (base64) on the led strip of pin
+    ///
+    /// arg is the color format (0 GRB, 1 GRBW), the answer val is the number
+    /// of leds written
+    fn process_led_write(&mut self, cmd: &Command) -> Answer {
+        let format = match CmdLedFormatValue::from_u8(cmd.arg) {
+            Some(CmdLedFormatValue::Grb)  => LedFormat::Grb,
+            Some(CmdLedFormatValue::Grbw) => LedFormat::Grbw,
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+
+        let mut colors = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let size = match Self::decode_cmd_data(cmd, &mut colors) {
+            Some(size) if size % format.bytes() == 0 => size,
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing or invalid dat").unwrap()),
+        };
+
+        let sys_clk_hz = self.cycles_per_us * 1_000_000;
+        match self.gpio_ctrl.borrow(cmd.pin) {
+            Some(io) => {
+                // Drive the line low, then give the pin to the pio
+                if io.try_into_mode(DYN_READABLE_OUTPUT).is_err() || io.set_low().is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot drive pin").unwrap());
+                }
+                if io.try_into_mode(DynPinMode::Function(DynFunction::Pio0)).is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to pio mode").unwrap());
+                }
+
+                let result = self.ws2812.write(&mut self.pio0, sys_clk_hz, cmd.pin, format, &colors[0..size]);
+
+                // Hold the line low to latch the colors
+                io.try_into_mode(DYN_READABLE_OUTPUT).ok();
+                io.set_low().ok();
+                self.delay.delay_us(ws2812::WS2812_LATCH_US);
+
+                match result {
+                    Ok(count) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("l").unwrap()).with_val(count as u32),
+                    Err(e)    => Self::answer_pio_error(cmd.pin, e),
                 }
             },
 
@@ -184,85 +929,921 @@ impl PicohaIo {
 
     // ------------------------------------------------------------------------
 
-    fn cmd_pin_get_value(io: &mut DynPin) -> Result<bool, CmdError> {
-        match io.is_high() {
-            Err(e) => Err(CmdError::HalError(e)),
-            Ok(v) => Ok(v)
+    /// To setup the stepper with pin as the STEP output
+    ///
+    /// prm holds [dir pin, max speed in steps/s, acceleration in steps/s^2,
+    /// 1 to invert the direction], the position is reset to 0
+    fn process_stepper_setup(&mut self, cmd: &Command) -> Answer {
+        let dir_pin = match cmd.prm_at(0) {
+            Some(x) if x >= 0 && x <= u8::MAX as i32 && x as u8 != cmd.pin => x as u8,
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid dir pin").unwrap()),
+        };
+        let max_speed    = cmd.prm_at(1).map(|x| x as u32).unwrap_or(DEFAULT_STEPPER_SPEED);
+        let accel        = cmd.prm_at(2).map(|x| x as u32).unwrap_or(DEFAULT_STEPPER_ACCEL);
+        let dir_inverted = cmd.prm_at(3).unwrap_or(0) != 0;
+
+        if self.stepper.state() != StepperState::Idle {
+            return Self::answer_stepper_error(cmd.pin, StepperError::Busy);
+        }
+        if self.gpio_ctrl.borrow(dir_pin).is_none() {
+            return Answer::error(dir_pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        // Release the pins of a previous setup
+        if let Some((step, dir)) = self.stepper.release(&mut self.pio1) {
+            self.release_stepper_pins(step, dir);
+        }
+
+        match self.gpio_ctrl.borrow(cmd.pin) {
+            Some(io) => {
+                if io.try_into_mode(DYN_READABLE_OUTPUT).is_err() || io.set_low().is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot drive pin").unwrap());
+                }
+                if io.try_into_mode(DynPinMode::Function(DynFunction::Pio1)).is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to pio mode").unwrap());
+                }
+            },
+            None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+        }
+        if let Some(io) = self.gpio_ctrl.borrow(dir_pin) {
+            if io.try_into_mode(DYN_READABLE_OUTPUT).is_err() {
+                self.release_stepper_pins(cmd.pin, dir_pin);
+                return Answer::error(dir_pin, 0, AnswerText::from_str("Cannot drive pin").unwrap());
+            }
+        }
+
+        let sys_clk_hz = self.cycles_per_us * 1_000_000;
+        match self.stepper.setup(sys_clk_hz, cmd.pin, dir_pin, dir_inverted, max_speed, accel) {
+            Ok(()) => Answer::ok(cmd.pin, 0, AnswerText::from_str("m").unwrap()),
+            Err(e) => {
+                self.release_stepper_pins(cmd.pin, dir_pin);
+                Self::answer_stepper_error(cmd.pin, e)
+            },
+        }
+    }
+
+    /// To start a move to the position prm[0]
+    ///
+    /// arg 0 for an absolute position, 1 for a position relative to the
+    /// current one. The answer val is the target position.
+    fn process_stepper_move(&mut self, cmd: &Command) -> Answer {
+        let position = match cmd.prm_at(0) {
+            Some(x) => x,
+            None    => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing position").unwrap()),
+        };
+        let target = match CmdStepperMoveValue::from_u8(cmd.arg) {
+            Some(CmdStepperMoveValue::Absolute) => position,
+            Some(CmdStepperMoveValue::Relative) => self.stepper.position().wrapping_add(position),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+
+        match self.stepper.move_to(&mut self.pio1, target) {
+            Ok(()) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("m").unwrap()).with_prm(&[target]),
+            Err(e) => Self::answer_stepper_error(cmd.pin, e),
         }
     }
 
-    /// To read an io
-    fn process_read_io(&mut self, cmd: &Command) -> Answer {
+    /// To start a homing move towards the limit input on pin
+    ///
+    /// arg is the active level of the limit (0 low, 1 high), val the speed in
+    /// steps/s and prm holds [direction (-1 or 1), max travel in steps]. The
+    /// position is set to 0 when the limit is reached.
+    fn process_stepper_home(&mut self, cmd: &Command) -> Answer {
+        let active_high = match CmdPinWriteValue::from_u8(cmd.arg) {
+            Some(CmdPinWriteValue::High) => true,
+            Some(CmdPinWriteValue::Low)  => false,
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+        let speed = match cmd.val {
+            Some(x) if x > 0 => x,
+            _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing val").unwrap()),
+        };
+        let direction = match cmd.prm_at(0) {
+            Some(x) if x > 0 => 1,
+            Some(x) if x < 0 => -1,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid direction").unwrap()),
+            None    => -1,
+        };
+        let max_steps = match cmd.prm_at(1) {
+            Some(x) if x > 0 => x as u32,
+            Some(_) => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid max travel").unwrap()),
+            None    => DEFAULT_HOMING_MAX_STEPS,
+        };
+
+        if self.stepper.state() != StepperState::Idle {
+            return Self::answer_stepper_error(cmd.pin, StepperError::Busy);
+        }
+
+        // The state machine jumps on a high level, invert active low limits
         match self.gpio_ctrl.borrow(cmd.pin) {
-            Some(io) => match Self::cmd_pin_get_value(io) {
-                Ok(v) => Answer::ok(
-                    cmd.pin,
-                    match v { true => 1, false => 0},
-                    AnswerText::from_str("r").unwrap(),
-                ),
-
-                Err(_) => Answer::error(
-                    cmd.pin,
-                    0,
-                    AnswerText::from_str("Cannot read pin value. Is direction correct?").unwrap()
-                )
+            Some(io) => {
+                let mode = match active_high {
+                    true  => DYN_PULL_DOWN_INPUT,
+                    false => DYN_PULL_UP_INPUT,
+                };
+                if io.try_into_mode(mode).is_err() {
+                    return Answer::error(cmd.pin, 0, AnswerText::from_str("Cannot set pin to input").unwrap());
+                }
+                gpio_ctrl::set_input_inverted(cmd.pin, !active_high);
             },
+            None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+        }
 
-            None => Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+        match self.stepper.home(&mut self.pio1, cmd.pin, speed, direction, max_steps) {
+            Ok(()) => {
+                self.limit_pin = Some(cmd.pin);
+                Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("m").unwrap())
+            },
+            Err(e) => {
+                gpio_ctrl::set_input_inverted(cmd.pin, false);
+                Self::answer_stepper_error(cmd.pin, e)
+            },
+        }
+    }
+
+    /// To get the stepper state
+    ///
+    /// The answer arg is the state (0 idle, 1 moving, 2 homing) and prm holds
+    /// [position, target, 1 if homed]
+    fn process_stepper_status(&mut self, cmd: &Command) -> Answer {
+        self.update_stepper();
+
+        Answer::ok(cmd.pin, self.stepper.state() as u8, AnswerText::from_str("m").unwrap())
+            .with_prm(&[
+                self.stepper.position(),
+                self.stepper.target(),
+                self.stepper.is_homed() as i32,
+            ])
+    }
+
+    /// To stop the stepper move
+    ///
+    /// arg 0 decelerates to a stop, arg 1 stops immediately (the position is
+    /// then lost until the next homing)
+    fn process_stepper_stop(&mut self, cmd: &Command) -> Answer {
+        match CmdStepperStopValue::from_u8(cmd.arg) {
+            Some(CmdStepperStopValue::Decelerate) => self.stepper.stop(&mut self.pio1),
+            Some(CmdStepperStopValue::Immediate)  => self.stepper.abort(&mut self.pio1),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        }
+        self.update_stepper();
+
+        Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("m").unwrap()).with_prm(&[self.stepper.target()])
+    }
+
+    /// To stop the stepper and release its pins as low outputs
+    fn process_stepper_release(&mut self, cmd: &Command) -> Answer {
+        if let Some((step, dir)) = self.stepper.release(&mut self.pio1) {
+            self.release_stepper_pins(step, dir);
+        }
+        self.update_stepper();
+
+        Answer::ok(cmd.pin, 0, AnswerText::from_str("m").unwrap())
+    }
+
+    /// Update the stepper and restore the limit input after homing
+    fn update_stepper(&mut self) {
+        self.stepper.update(&mut self.pio1);
+
+        if self.stepper.state() != StepperState::Homing {
+            if let Some(pin) = self.limit_pin.take() {
+                gpio_ctrl::set_input_inverted(pin, false);
+            }
+        }
+    }
+
+    /// Give the stepper pins back to the sio as low outputs
+    fn release_stepper_pins(&mut self, step: u8, dir: u8) {
+        for pin in [step, dir] {
+            if let Some(io) = self.gpio_ctrl.borrow(pin) {
+                io.try_into_mode(DYN_READABLE_OUTPUT).ok();
+                io.set_low().ok();
+            }
+        }
+    }
+
+    /// Build the error answer of a stepper failure
+    fn answer_stepper_error(pin: u8, err: StepperError) -> Answer {
+        match err {
+            StepperError::Pio(e)        => Self::answer_pio_error(pin, e),
+            StepperError::NotConfigured => Answer::error(pin, 0, AnswerText::from_str("Stepper not configured").unwrap()),
+            StepperError::Busy          => Answer::error(pin, 0, AnswerText::from_str("Stepper busy").unwrap()),
+            StepperError::InvalidSpeed  => Answer::error(pin, 0, AnswerText::from_str("Invalid speed or acceleration").unwrap()),
         }
     }
 
     // ------------------------------------------------------------------------
 
-    /// Process incoming commands
+    /// Run a transaction on the 1-Wire bus of the pin
     ///
-    pub fn update_command_processing(&mut self) -> Option<Answer> {
-        let mut cmd_buffer = [0u8; 512];
+    /// The pin goes back to a pull-up input at the end, the bus idle level.
+    fn onewire_run<F, R>(&mut self, pin: u8, transaction: F) -> Result<R, Answer>
+    where
+        F: FnOnce(&mut OneWireBus) -> Result<R, OneWireError>,
+    {
+        let sys_clk_hz = self.cycles_per_us * 1_000_000;
+        match self.gpio_ctrl.borrow(pin) {
+            Some(io) => {
+                if io.try_into_mode(DynPinMode::Function(DynFunction::Pio1)).is_err() {
+                    return Err(Answer::error(pin, 0, AnswerText::from_str("Cannot set pin to pio mode").unwrap()));
+                }
+
+                let result = self.onewire.run(&mut self.pio1, sys_clk_hz, pin, transaction);
+
+                io.try_into_mode(DYN_PULL_UP_INPUT).ok();
+                result.map_err(|e| Self::answer_onewire_error(pin, e))
+            },
 
-        match self.usb_buffer.get_command(&mut cmd_buffer) {
+            None => Err(Answer::error(pin, 0, AnswerText::from_str("Invalid pin").unwrap())),
+        }
+    }
+
+    /// To reset the 1-Wire bus, the answer arg is 1 if a device is present
+    fn process_onewire_reset(&mut self, cmd: &Command) -> Answer {
+        match self.onewire_run(cmd.pin, |bus| Ok(bus.reset())) {
+            Ok(presence) => Answer::ok(cmd.pin, presence as u8, AnswerText::from_str("w").unwrap()),
+            Err(answer)  => answer,
+        }
+    }
+
+    /// To write the bytes of dat (base64) then read val bytes on the bus
+    ///
+    /// arg 1 resets the bus first and fails without presence. The read bytes
+    /// are in the answer dat.
+    fn process_onewire_transfer(&mut self, cmd: &Command) -> DataAnswer {
+        let mut write = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let write_size = match &cmd.dat {
+            Some(_) => match Self::decode_cmd_data(cmd, &mut write) {
+                Some(size) => size,
+                None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid dat").unwrap()).into(),
+            },
+            None => 0,
+        };
+        let read_size = cmd.val.unwrap_or(0) as usize;
+        if read_size > DATA_ANSWER_MAX_BYTES {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Too many bytes to read").unwrap()).into();
+        }
+        let reset = match cmd.arg {
+            0 => false,
+            1 => true,
+            _ => return Self::answer_invalid_arg(cmd.pin, cmd.arg).into(),
+        };
+
+        let mut read = [0u8; DATA_ANSWER_MAX_BYTES];
+        let result = self.onewire_run(cmd.pin, |bus| {
+            if reset && !bus.reset() {
+                return Err(OneWireError::NoPresence);
+            }
+            write[0..write_size].iter().for_each(|&b| bus.write_byte(b));
+            read[0..read_size].iter_mut().for_each(|b| *b = bus.read_byte());
+            Ok(())
+        });
+
+        match result {
+            Ok(())      => Self::data_answer(cmd.pin, cmd.arg, "w", read_size as u32, &read[0..read_size]),
+            Err(answer) => answer.into(),
+        }
+    }
+
+    /// To list the rom codes of the devices on the bus
+    ///
+    /// arg 1 only lists the devices in alarm state. The answer val is the
+    /// number of devices and dat holds their 8 bytes rom codes.
+    fn process_onewire_search(&mut self, cmd: &Command) -> DataAnswer {
+        let alarm = match cmd.arg {
+            0 => false,
+            1 => true,
+            _ => return Self::answer_invalid_arg(cmd.pin, cmd.arg).into(),
+        };
+
+        let mut roms: [RomCode; ONEWIRE_MAX_DEVICES] = [[0; 8]; ONEWIRE_MAX_DEVICES];
+        match self.onewire_run(cmd.pin, |bus| bus.search(alarm, &mut roms)) {
+            Ok(count) => {
+                let mut raw = [0u8; ONEWIRE_MAX_DEVICES * 8];
+                for (chunk, rom) in raw.chunks_exact_mut(8).zip(roms[0..count].iter()) {
+                    chunk.copy_from_slice(rom);
+                }
+                Self::data_answer(cmd.pin, cmd.arg, "w", count as u32, &raw[0..count * 8])
+            },
+            Err(answer) => answer.into(),
+        }
+    }
+
+    /// To read the temperature of a DS18B20
+    ///
+    /// dat optionally holds the rom code (base64) of the device, the only
+    /// device of the bus is used otherwise. The answer val is the raw value
+    /// (1/16 degree) and prm[0] the temperature in millidegrees Celsius.
+    fn process_onewire_temperature(&mut self, cmd: &Command) -> Answer {
+        let mut raw = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let rom: Option<RomCode> = match &cmd.dat {
+            Some(_) => match Self::decode_cmd_data(cmd, &mut raw) {
+                Some(8) => Some([raw[0], raw[1], raw[2], raw[3], raw[4], raw[5], raw[6], raw[7]]),
+                _ => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid rom code").unwrap()),
+            },
             None => None,
-            Some(cmd_end_index) => {
-                let cmd_slice_ref = &cmd_buffer[0..cmd_end_index];
+        };
 
-                match serde_json_core::de::from_slice::<Command>(cmd_slice_ref) {
-                    // Process parsing error
-                    Err(_e) => {
-                        let mut txt = AnswerText::new();
-                        write!(txt, "Error: {}", _e).unwrap();
+        match self.onewire_run(cmd.pin, |bus| bus.ds18b20_temperature(rom.as_ref())) {
+            Ok(t) => Answer::ok(cmd.pin, 0, AnswerText::from_str("w").unwrap())
+                .with_val(t as u16 as u32)
+                .with_prm(&[t as i32 * 1000 / 16]),
+            Err(answer) => answer,
+        }
+    }
 
-                        Some(Answer::error(0, 0, txt))
-                    },
+    /// Build the error answer of a 1-Wire failure
+    fn answer_onewire_error(pin: u8, err: OneWireError) -> Answer {
+        match err {
+            OneWireError::Pio(e)     => Self::answer_pio_error(pin, e),
+            OneWireError::NoPresence => Answer::error(pin, 0, AnswerText::from_str("No 1-Wire device present").unwrap()),
+            OneWireError::Crc        => Answer::error(pin, 0, AnswerText::from_str("1-Wire crc error").unwrap()),
+            OneWireError::Timeout    => Answer::error(pin, 0, AnswerText::from_str("1-Wire conversion timeout").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To emulate an i2c device at the address val
+    ///
+    /// pin is the SDA pin (even gpio), SCL is the next pin
+    fn process_i2c_target_start(&mut self, cmd: &Command) -> Answer {
+        let address = match cmd.val {
+            Some(x) if x <= 0x7F => x as u8,
+            _ => return Self::answer_i2c_target_error(cmd.pin, I2cTargetError::InvalidAddress),
+        };
+        if let Err(e) = I2cTarget::sda_block(cmd.pin) {
+            return Self::answer_i2c_target_error(cmd.pin, e);
+        }
+        if self.i2c_target.is_active() {
+            return Self::answer_i2c_target_error(cmd.pin, I2cTargetError::Busy);
+        }
+
+        for pin in [cmd.pin, cmd.pin + 1] {
+            match self.gpio_ctrl.borrow(pin) {
+                Some(io) => {
+                    if io.try_into_mode(DynPinMode::Function(DynFunction::I2C)).is_err() {
+                        self.release_i2c_target_pins(cmd.pin);
+                        return Answer::error(pin, 0, AnswerText::from_str("Cannot set pin to i2c mode").unwrap());
+                    }
+                },
+                None => return Answer::error(pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+            }
+        }
+
+        match self.i2c_target.start(cmd.pin, address) {
+            Ok(()) => Answer::ok(cmd.pin, 0, AnswerText::from_str("i").unwrap()).with_val(address as u32),
+            Err(e) => {
+                self.release_i2c_target_pins(cmd.pin);
+                Self::answer_i2c_target_error(cmd.pin, e)
+            },
+        }
+    }
+
+    /// To load the bytes of dat (base64) in the register map at offset val
+    fn process_i2c_target_load(&mut self, cmd: &Command) -> Answer {
+        let mut raw = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let size = match Self::decode_cmd_data(cmd, &mut raw) {
+            Some(size) => size,
+            None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing or invalid dat").unwrap()),
+        };
+
+        match self.i2c_target.load(cmd.val.unwrap_or(0) as usize, &raw[0..size]) {
+            Ok(()) => Answer::ok(cmd.pin, 0, AnswerText::from_str("i").unwrap()).with_val(size as u32),
+            Err(e) => Self::answer_i2c_target_error(cmd.pin, e),
+        }
+    }
+
+    /// To read prm[0] bytes (all by default) of the register map at offset val
+    fn process_i2c_target_dump(&mut self, cmd: &Command) -> DataAnswer {
+        let offset = cmd.val.unwrap_or(0) as usize;
+        let size = match cmd.prm_at(0) {
+            Some(x) if x >= 0 => x as usize,
+            Some(_) => return Self::answer_i2c_target_error(cmd.pin, I2cTargetError::OutOfMap).into(),
+            None    => i2c_target::I2C_TARGET_MAP_SIZE.saturating_sub(offset),
+        };
+
+        match self.i2c_target.dump(offset, size) {
+            Ok(data) => Self::data_answer(cmd.pin, 0, "i", offset as u32, data),
+            Err(e)   => Self::answer_i2c_target_error(cmd.pin, e).into(),
+        }
+    }
+
+    /// To fetch the events recorded since the last fetch
+    ///
+    /// The answer val is the number of events, arg is 1 if events have been
+    /// dropped, dat holds the 4 bytes events [kind, register, value, 0]
+    fn process_i2c_target_events(&mut self, cmd: &Command) -> DataAnswer {
+        let mut raw = [0u8; DATA_ANSWER_MAX_BYTES];
+        let (count, overflow) = self.i2c_target.fetch_events(&mut raw);
+        let size = count * i2c_target::EVENT_BYTES;
+
+        Self::data_answer(cmd.pin, overflow as u8, "i", count as u32, &raw[0..size])
+    }
+
+    /// To stop the i2c device emulation, the pins go back to inputs
+    fn process_i2c_target_stop(&mut self, cmd: &Command) -> Answer {
+        if let Some(sda) = self.i2c_target.stop() {
+            self.release_i2c_target_pins(sda);
+        }
+        Answer::ok(cmd.pin, 0, AnswerText::from_str("i").unwrap())
+    }
+
+    /// Give the SDA and SCL pins back to the sio as pull-up inputs
+    fn release_i2c_target_pins(&mut self, sda: u8) {
+        for pin in [sda, sda + 1] {
+            if let Some(io) = self.gpio_ctrl.borrow(pin) {
+                io.try_into_mode(DYN_PULL_UP_INPUT).ok();
+            }
+        }
+    }
+
+    /// Build the error answer of an i2c target failure
+    fn answer_i2c_target_error(pin: u8, err: I2cTargetError) -> Answer {
+        match err {
+            I2cTargetError::InvalidPin     => Answer::error(pin, 0, AnswerText::from_str("Pin is not an i2c SDA pin").unwrap()),
+            I2cTargetError::InvalidAddress => Answer::error(pin, 0, AnswerText::from_str("Invalid i2c address").unwrap()),
+            I2cTargetError::Busy           => Answer::error(pin, 0, AnswerText::from_str("I2c target already started").unwrap()),
+            I2cTargetError::OutOfMap       => Answer::error(pin, 0, AnswerText::from_str("Out of the register map").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To emulate a spi device on the 4 pins group starting at pin
+    ///
+    /// The group is RX (MOSI), CSn, SCK, TX (MISO), arg is the spi mode (0-3)
+    fn process_spi_target_start(&mut self, cmd: &Command) -> Answer {
+        if let Err(e) = SpiTarget::group_block(cmd.pin) {
+            return Self::answer_spi_target_error(cmd.pin, e);
+        }
+        if self.spi_target.is_active() {
+            return Self::answer_spi_target_error(cmd.pin, SpiTargetError::Busy);
+        }
+
+        for pin in cmd.pin..cmd.pin + 4 {
+            match self.gpio_ctrl.borrow(pin) {
+                Some(io) => {
+                    if io.try_into_mode(DynPinMode::Function(DynFunction::Spi)).is_err() {
+                        self.release_spi_target_pins(cmd.pin);
+                        return Answer::error(pin, 0, AnswerText::from_str("Cannot set pin to spi mode").unwrap());
+                    }
+                },
+                None => return Answer::error(pin, 0, AnswerText::from_str("Invalid pin").unwrap()),
+            }
+        }
+
+        match self.spi_target.start(cmd.pin, cmd.arg) {
+            Ok(()) => Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("s").unwrap()),
+            Err(e) => {
+                self.release_spi_target_pins(cmd.pin);
+                Self::answer_spi_target_error(cmd.pin, e)
+            },
+        }
+    }
+
+    /// To load the bytes of dat (base64) in the response buffer at offset val
+    ///
+    /// The response ends after the loaded bytes
+    fn process_spi_target_load(&mut self, cmd: &Command) -> Answer {
+        let mut raw = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let size = match Self::decode_cmd_data(cmd, &mut raw) {
+            Some(size) => size,
+            None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Missing or invalid dat").unwrap()),
+        };
+
+        let offset = cmd.val.unwrap_or(0) as usize;
+        match self.spi_target.load(offset, &raw[0..size]) {
+            Ok(()) => Answer::ok(cmd.pin, 0, AnswerText::from_str("s").unwrap()).with_val((offset + size) as u32),
+            Err(e) => Self::answer_spi_target_error(cmd.pin, e),
+        }
+    }
+
+    /// To fetch the transaction log recorded since the last fetch
+    ///
+    /// The answer val is the number of bytes, arg is 1 if bytes have been
+    /// dropped and dat holds the log in base64
+    fn process_spi_target_log(&mut self, cmd: &Command) -> DataAnswer {
+        let mut raw = [0u8; DATA_ANSWER_MAX_BYTES];
+        let (size, overflow) = self.spi_target.fetch_log(&mut raw);
+
+        Self::data_answer(cmd.pin, overflow as u8, "s", size as u32, &raw[0..size])
+    }
+
+    /// To stop the spi device emulation, the pins go back to inputs
+    fn process_spi_target_stop(&mut self, cmd: &Command) -> Answer {
+        if let Some(base) = self.spi_target.stop() {
+            self.release_spi_target_pins(base);
+        }
+        Answer::ok(cmd.pin, 0, AnswerText::from_str("s").unwrap())
+    }
+
+    /// Give the pins of the group back to the sio as pull-down inputs
+    fn release_spi_target_pins(&mut self, base: u8) {
+        for pin in base..base + 4 {
+            if let Some(io) = self.gpio_ctrl.borrow(pin) {
+                io.try_into_mode(DYN_PULL_DOWN_INPUT).ok();
+            }
+        }
+    }
+
+    /// Build the error answer of a spi target failure
+    fn answer_spi_target_error(pin: u8, err: SpiTargetError) -> Answer {
+        match err {
+            SpiTargetError::InvalidPin  => Answer::error(pin, 0, AnswerText::from_str("Pin is not the first pin of a spi group").unwrap()),
+            SpiTargetError::InvalidMode => Answer::error(pin, 0, AnswerText::from_str("Invalid spi mode").unwrap()),
+            SpiTargetError::Busy        => Answer::error(pin, 0, AnswerText::from_str("Spi target already started").unwrap()),
+            SpiTargetError::OutOfBuffer => Answer::error(pin, 0, AnswerText::from_str("Out of the response buffer").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To reboot the device once the answer is sent
+    ///
+    /// arg 0 restarts the firmware, arg 1 restarts in the usb bootloader
+    fn process_reboot(&mut self, cmd: &Command) -> Answer {
+        self.reboot = match CmdRebootValue::from_u8(cmd.arg) {
+            Some(CmdRebootValue::Soft)       => Some(RebootRequest::Soft),
+            Some(CmdRebootValue::Bootloader) => Some(RebootRequest::Bootloader),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+        Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("Rebooting").unwrap())
+    }
+
+    /// Take the reboot requested by the last command
+    pub fn take_reboot_request(&mut self) -> Option<RebootRequest> {
+        self.reboot.take()
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To select when the safe state is applied
+    ///
+    /// arg 0 never, arg 1 on usb suspend and disconnection
+    fn process_safe_state_mode(&mut self, cmd: &Command) -> Answer {
+        self.safe_state = match CmdSafeStateValue::from_u8(cmd.arg) {
+            Some(CmdSafeStateValue::Disabled) => false,
+            Some(CmdSafeStateValue::Enabled)  => true,
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+        Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("Safe state mode set").unwrap())
+    }
+
+    /// The usb bus has been reset, or the cable unplugged
+    ///
+    /// The partial commands are dropped and the json protocol is selected
+    /// again.
+    pub fn usb_reset(&mut self) {
+        self.commands.reset();
+        if self.safe_state {
+            self.apply_safe_state();
+        }
+    }
+
+    /// The host suspended the usb bus
+    pub fn usb_suspend(&mut self) {
+        if self.safe_state {
+            self.apply_safe_state();
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Levels of all the gpios, bit X => gpioX
+    #[cfg(feature = "hid")]
+    pub fn input_levels(&self) -> u32 {
+        // Safety: read only access to the input levels
+        unsafe { (*pac::SIO::ptr()).gpio_in.read().bits() }
+    }
+
+    /// Set the levels of the gpios in the mask, bit X => gpioX
+    ///
+    /// Only the pins in output mode are written, the other ones are ignored.
+    #[cfg(feature = "hid")]
+    pub fn write_outputs(&mut self, mask: u32, levels: u32) {
+        for idx in 0..32u8 {
+            if mask & (1 << idx) == 0 {
+                continue;
+            }
+            if let Some(io) = self.gpio_ctrl.borrow(idx) {
+                match levels & (1 << idx) != 0 {
+                    true  => io.set_high().ok(),
+                    false => io.set_low().ok(),
+                };
+            }
+        }
+    }
 
-                    // Process received command
-                    Ok(cmd) => {
-                        let data = &cmd.0;
-
-                        match CommandCode::from_u8(data.cod) {
-                            Some(x) => match x {
-                                CommandCode::SetDirection => Some(self.process_set_io_mode(data)),
-                                CommandCode::WriteValue   => Some(self.process_write_io(data)),
-                                CommandCode::ReadValue    => Some(self.process_read_io(data)),
-                                CommandCode::Test         => Some(Answer::ok(0, 1, AnswerText::from_str("").unwrap())),
-                            },
-
-                            None => {
-                                let mut txt = AnswerText::new();
-                                write!(txt, "Uknown command code: {}", data.cod).unwrap();
-
-                                Some(Answer::error(0, 0, txt))
-                            },
-                        }
+    // ------------------------------------------------------------------------
+
+    /// To set the label (arg 0) or the serial number (arg 1) of the device
+    ///
+    /// dat holds the text in base64, the field is cleared when dat is
+    /// missing. The identity is stored in flash and used on usb from the next
+    /// boot.
+    fn process_identity_set(&mut self, cmd: &Command) -> Answer {
+        let mut raw = [0u8; protocol::MAX_CMD_DAT_SIZE];
+        let size = match &cmd.dat {
+            Some(_) => match Self::decode_cmd_data(cmd, &mut raw) {
+                Some(size) => size,
+                None => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid dat").unwrap()),
+            },
+            None => 0,
+        };
+        let text = match core::str::from_utf8(&raw[0..size]) {
+            Ok(text) => text,
+            Err(_)   => return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid text").unwrap()),
+        };
+
+        let result = match CmdIdentityValue::from_u8(cmd.arg) {
+            Some(CmdIdentityValue::Label)  => self.identity.set_label(text),
+            Some(CmdIdentityValue::Serial) => self.identity.set_serial(text),
+            None => return Self::answer_invalid_arg(cmd.pin, cmd.arg),
+        };
+        match result {
+            Ok(()) => {
+                self.identity.store();
+                Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("Stored, applied on usb at next boot").unwrap())
+            },
+            Err(IdentityError::TooLong)     => Answer::error(cmd.pin, 0, AnswerText::from_str("Text too long").unwrap()),
+            Err(IdentityError::InvalidChar) => Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid character").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Build a data answer, the data is encoded in base64
+    fn data_answer(pin: u8, arg: u8, msg: &str, val: u32, raw: &[u8]) -> DataAnswer {
+        let mut encoded = [0u8; protocol::MAX_DAT_SIZE];
+        let len = base64::encode_config_slice(raw, base64::STANDARD, &mut encoded);
+
+        let mut answer = DataAnswer::from(Answer::ok(pin, arg, AnswerText::from_str(msg).unwrap()).with_val(val));
+        // base64 output is always valid ascii
+        answer.dat.push_str(core::str::from_utf8(&encoded[0..len]).unwrap()).unwrap();
+        answer
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Build the error answer of an invalid argument
+    fn answer_invalid_arg(pin: u8, arg: u8) -> Answer {
+        let mut txt = AnswerText::new();
+        write!(txt, "Invalid arg: {}", arg).unwrap();
+
+        Answer::error(pin, 0, txt)
+    }
+
+    /// Build the error answer of a pio failure
+    fn answer_pio_error(pin: u8, err: PioError) -> Answer {
+        match err {
+            PioError::Busy    => Answer::error(pin, 0, AnswerText::from_str("PIO state machine busy").unwrap()),
+            PioError::NoSpace => Answer::error(pin, 0, AnswerText::from_str("No space left in PIO memory").unwrap()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Process a frame of the vendor bulk interface
+    ///
+    /// The answers are given to `send` as frames (kind, payload).
+    #[cfg(feature = "vendor")]
+    pub fn process_frame(&mut self, kind: u8, payload: &[u8], send: &mut dyn FnMut(u8, &[u8])) {
+        match FrameKind::from_u8(kind) {
+            Some(FrameKind::Json) => {
+                let response = self.process_line(payload);
+                Self::send_json_frame(&response, send);
+            },
+            Some(FrameKind::CaptureRead) => self.process_capture_stream(payload, send),
+            Some(FrameKind::PatternLoad) => {
+                let answer = match payload.len() >= 4 {
+                    true  => {
+                        let offset = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                        self.pattern_load(0, offset as usize, &payload[4..])
                     },
-                }
+                    false => Answer::error(0, 0, AnswerText::from_str("Missing step offset").unwrap()),
+                };
+                Self::send_json_frame(&Response::Single(answer), send);
+            },
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Unknown frame kind: {}", kind).unwrap();
+                Self::send_json_frame(&Response::Single(Answer::error(0, 0, txt)), send);
+            },
+        }
+    }
+
+    /// Answer the bytes dropped by the frame resynchronisation
+    #[cfg(feature = "vendor")]
+    pub fn process_invalid_frame(&mut self, err: FrameError, send: &mut dyn FnMut(u8, &[u8])) {
+        let msg = match err {
+            FrameError::InvalidHeader => "Invalid frame header",
+            FrameError::Timeout       => "Incomplete frame",
+        };
+        let answer = Answer::error(0, 0, AnswerText::from_str(msg).unwrap());
+        Self::send_json_frame(&Response::Single(answer), send);
+    }
+
+    /// Stream capture chunks, the payload holds the first chunk index and
+    /// the number of chunks (u32 little endian each)
+    ///
+    /// Each chunk is sent in its own frame, after its index (u32 little
+    /// endian). An error answer ends the stream early.
+    #[cfg(feature = "vendor")]
+    fn process_capture_stream(&mut self, payload: &[u8], send: &mut dyn FnMut(u8, &[u8])) {
+        if payload.len() < 8 {
+            let answer = Answer::error(0, 0, AnswerText::from_str("Missing chunk range").unwrap());
+            return Self::send_json_frame(&Response::Single(answer), send);
+        }
+        let first = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        let count = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+
+        let mut raw = [0u8; capture::CHUNK_BYTES];
+        let mut frame = [0u8; 4 + capture::CHUNK_BYTES];
+        for index in first..first.saturating_add(count) {
+            match self.capture.read_chunk(index, &mut raw) {
+                Ok(size) => {
+                    frame[0..4].copy_from_slice(&index.to_le_bytes());
+                    frame[4..4 + size].copy_from_slice(&raw[0..size]);
+                    send(FrameKind::CaptureRead as u8, &frame[0..4 + size]);
+                },
+                Err(_) => {
+                    let answer = Answer::error(0, 0, AnswerText::from_str("Invalid chunk").unwrap()).with_val(index);
+                    return Self::send_json_frame(&Response::Single(answer), send);
+                },
             }
         }
     }
 
+    /// Send a response in a json frame
+    #[cfg(feature = "vendor")]
+    fn send_json_frame(response: &Response, send: &mut dyn FnMut(u8, &[u8])) {
+        let mut buffer = [0u8; 4096];
+        let size = response.serialize(&mut buffer);
+        send(FrameKind::Json as u8, &buffer[0..size]);
+    }
+
     // ------------------------------------------------------------------------
 
-    /// Feed input buffer
+    /// True if the pin is used by a running input function (counter,
+    /// quadrature decoder or capture)
+    fn is_input_function_pin(&self, pin: u8) -> bool {
+        let captured = self.capture.state() == CaptureState::Running && self.capture.pin_mask() & (1 << pin) != 0;
+        let decoded = self.quadrature.is_running_on(pin) || (pin > 0 && self.quadrature.is_running_on(pin - 1));
+        self.counters.is_counting_on(pin) || decoded || captured
+    }
+
+    /// Update the background tasks
     ///
-    pub fn feed_cmd_buffer(&mut self, buf: &[u8], count: usize) {
-        self.usb_buffer.load(buf, count);
+    /// Must be called from the main loop as often as possible
+    pub fn update_tasks(&mut self) {
+        self.counters.update(&self.pwm);
+        self.servos.update(&self.pwm, self.timer.get_counter());
+        self.quadrature.update(&self.timer);
+        self.capture.update(&mut self.pio0, &self.dma);
+        self.pattern.update(&self.dma);
+        self.update_stepper();
+        self.i2c_target.update();
+        self.spi_target.update(self.timer.get_counter());
+    }
+}
+
+// ============================================================================
+
+/// The protocol handling is shared with the host builds
+impl CommandProcessor for PicohaIo {
+    type Pins = GpioController;
+
+    fn state(&self) -> &ProcessorState {
+        &self.commands
+    }
+
+    fn state_mut(&mut self) -> &mut ProcessorState {
+        &mut self.commands
+    }
+
+    fn pins(&mut self) -> &mut GpioController {
+        &mut self.gpio_ctrl
+    }
+
+    /// Time since boot (in us)
+    fn now_us(&self) -> u64 {
+        self.timer.get_counter()
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
+    }
+
+    /// Put the device in its safe state
+    ///
+    /// The output generators and the bus targets are stopped, then every
+    /// gpio becomes a pull-down input. The inputs (counters, decoder,
+    /// capture) keep running, their pins are left untouched.
+    fn apply_safe_state(&mut self) {
+        if self.pattern.is_active() {
+            self.pattern.stop(&mut self.pio1, &self.dma);
+        }
+        self.stepper.release(&mut self.pio1);
+        self.update_stepper();
+        for pin in 0..30 {
+            self.servos.stop(&self.pwm, pin).ok();
+        }
+        self.i2c_target.stop();
+        self.spi_target.stop();
+
+        for pin in 0..30 {
+            if self.is_input_function_pin(pin) {
+                continue;
+            }
+            if let Some(io) = self.gpio_ctrl.borrow(pin) {
+                io.try_into_mode(DYN_PULL_DOWN_INPUT).ok();
+            }
+        }
+    }
+
+    /// To identify the device, answers with its label and serial number
+    fn process_test(&self) -> InfoAnswer {
+        let mut answer = InfoAnswer {
+            sts: AnswerStatus::Ok,
+            pin: 0,
+            arg: 1,
+            msg: AnswerText::new(),
+            lbl: String::new(),
+            ser: String::new(),
+        };
+        answer.lbl.push_str(self.identity.label()).unwrap();
+        answer.ser.push_str(self.identity.usb_serial_number().as_str()).unwrap();
+        answer
+    }
+
+    fn write_identification(&self, reply: &mut ScpiReply) {
+        write!(reply, "{},{},{},{}",
+            USB_MANUFACTURER_NAME,
+            self.identity.usb_product_name(),
+            self.identity.usb_serial_number(),
+            env!("CARGO_PKG_VERSION"),
+        ).ok();
+    }
+
+    fn execute_device_command(&mut self, code: CommandCode, cmd: &Command) -> Answer {
+        match code {
+            CommandCode::Pulse              => self.process_pulse(cmd),
+            CommandCode::Measure            => self.process_measure(cmd),
+            CommandCode::CounterStart       => self.process_counter_start(cmd),
+            CommandCode::CounterRead        => self.process_counter_read(cmd),
+            CommandCode::CounterStop        => self.process_counter_stop(cmd),
+            CommandCode::QuadStart          => self.process_quad_start(cmd),
+            CommandCode::QuadRead           => self.process_quad_read(cmd),
+            CommandCode::QuadPreset         => self.process_quad_preset(cmd),
+            CommandCode::QuadStop           => self.process_quad_stop(cmd),
+            CommandCode::CaptureStart       => self.process_capture_start(cmd),
+            CommandCode::CaptureStatus      => self.process_capture_status(cmd),
+            CommandCode::CaptureStop        => self.process_capture_stop(cmd),
+            CommandCode::PatternLoad        => self.process_pattern_load(cmd),
+            CommandCode::PatternStart       => self.process_pattern_start(cmd),
+            CommandCode::PatternStop        => self.process_pattern_stop(cmd),
+            CommandCode::PatternStatus      => self.process_pattern_status(cmd),
+            CommandCode::LedWrite           => self.process_led_write(cmd),
+            CommandCode::ServoStart         => self.process_servo_start(cmd),
+            CommandCode::ServoSet           => self.process_servo_set(cmd),
+            CommandCode::ServoRead          => self.process_servo_read(cmd),
+            CommandCode::ServoStop          => self.process_servo_stop(cmd),
+            CommandCode::StepperSetup       => self.process_stepper_setup(cmd),
+            CommandCode::StepperMove        => self.process_stepper_move(cmd),
+            CommandCode::StepperHome        => self.process_stepper_home(cmd),
+            CommandCode::StepperStatus      => self.process_stepper_status(cmd),
+            CommandCode::StepperStop        => self.process_stepper_stop(cmd),
+            CommandCode::StepperRelease     => self.process_stepper_release(cmd),
+            CommandCode::OneWireReset       => self.process_onewire_reset(cmd),
+            CommandCode::OneWireTemperature => self.process_onewire_temperature(cmd),
+            CommandCode::I2cTargetStart     => self.process_i2c_target_start(cmd),
+            CommandCode::I2cTargetLoad      => self.process_i2c_target_load(cmd),
+            CommandCode::I2cTargetStop      => self.process_i2c_target_stop(cmd),
+            CommandCode::SpiTargetStart     => self.process_spi_target_start(cmd),
+            CommandCode::SpiTargetLoad      => self.process_spi_target_load(cmd),
+            CommandCode::SpiTargetStop      => self.process_spi_target_stop(cmd),
+            CommandCode::Reboot             => self.process_reboot(cmd),
+            CommandCode::IdentitySet        => self.process_identity_set(cmd),
+            CommandCode::SafeStateMode      => self.process_safe_state_mode(cmd),
+            _ => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Uknown command code: {}", cmd.cod).unwrap();
+
+                Answer::error(0, 0, txt)
+            },
+        }
+    }
+
+    fn process_data_command(&mut self, code: CommandCode, cmd: &Command) -> DataAnswer {
+        match code {
+            CommandCode::CaptureRead     => self.process_capture_read(cmd),
+            CommandCode::OneWireTransfer => self.process_onewire_transfer(cmd),
+            CommandCode::OneWireSearch   => self.process_onewire_search(cmd),
+            CommandCode::I2cTargetDump   => self.process_i2c_target_dump(cmd),
+            CommandCode::I2cTargetEvents => self.process_i2c_target_events(cmd),
+            CommandCode::SpiTargetLog    => self.process_spi_target_log(cmd),
+            _ => DataAnswer::from(Answer::error(0, 0, AnswerText::from_str("Not a data command").unwrap())),
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac::PIO1;
+use rp_pico::hal::pio::{ShiftDirection, PIO, SM3};
+
+use super::pio_slot::{PioError, PioSlot, PioTask};
+
+// ============================================================================
+
+/// The state machine runs with a 1 us tick
+const TICK_HZ: u32 = 1_000_000;
+
+/// Operation words sent to the state machine
+const OP_SLOT: u32 = 0;
+const OP_RESET: u32 = 1;
+
+/// Rom commands
+const CMD_SEARCH_ROM: u8 = 0xF0;
+const CMD_ALARM_SEARCH: u8 = 0xEC;
+const CMD_MATCH_ROM: u8 = 0x55;
+const CMD_SKIP_ROM: u8 = 0xCC;
+
+/// DS18B20 function commands
+const CMD_CONVERT_T: u8 = 0x44;
+const CMD_READ_SCRATCHPAD: u8 = 0xBE;
+
+/// Max duration of a DS18B20 conversion, in read slots of about 70 us
+const CONVERSION_MAX_SLOTS: u32 = 12_000;
+
+/// Errors of the 1-Wire operations
+pub enum OneWireError {
+    /// Pio resource error
+    Pio(PioError),
+
+    /// No device answered the reset
+    NoPresence,
+
+    /// Invalid crc on the data read
+    Crc,
+
+    /// The conversion did not end in time
+    Timeout,
+}
+
+impl From<PioError> for OneWireError {
+    fn from(e: PioError) -> Self {
+        Self::Pio(e)
+    }
+}
+
+/// Rom code of a device
+pub type RomCode = [u8; 8];
+
+// ============================================================================
+
+/// 1-Wire bus master with pio timings
+///
+/// The line is open drain: the pin output level is always low and the state
+/// machine drives it by switching the pin direction. An external pull-up is
+/// needed.
+pub struct OneWireMaster {
+    /// State machine used to time the slots
+    slot: PioSlot<PIO1, SM3>,
+}
+
+/// Handle on the bus during a transaction
+pub struct OneWireBus<'a> {
+    task: &'a mut PioTask<PIO1, SM3>,
+}
+
+// ============================================================================
+
+impl OneWireMaster {
+    ///
+    pub fn new(slot: PioSlot<PIO1, SM3>) -> Self {
+        Self {
+            slot: slot,
+        }
+    }
+
+    /// Run a transaction on the bus of the pin
+    ///
+    /// The pin must already be in pio1 function mode.
+    pub fn run<F, R>(
+        &mut self,
+        pio: &mut PIO<PIO1>,
+        sys_clk_hz: u32,
+        pin: u8,
+        transaction: F,
+    ) -> Result<R, OneWireError>
+    where
+        F: FnOnce(&mut OneWireBus) -> Result<R, OneWireError>,
+    {
+        let program = pio_proc::pio_asm!(
+            "    set pins, 0",          // the line is only driven low
+            ".wrap_target",
+            "start:",
+            "    pull block",
+            "    out x, 1",
+            "    jmp !x slot",
+            // Reset: low 496 us, sample the presence 70 us after the release
+            "    set y, 15",
+            "reset_low:",
+            "    set pindirs, 1 [29]",
+            "    jmp y-- reset_low",
+            "    set pindirs, 0 [31]",
+            "    nop [31]",
+            "    nop [5]",
+            "    in pins, 1",
+            "    set y, 13",
+            "reset_wait:",
+            "    jmp y-- reset_wait [29]",
+            "    push block",
+            "    jmp start",
+            // Slot: low 6 us, then the inverted bit until 60 us, sample at 15 us
+            "slot:",
+            "    set pindirs, 1 [5]",
+            "    out pindirs, 1 [8]",
+            "    in pins, 1 [29]",
+            "    nop [14]",
+            "    set pindirs, 0 [9]",
+            "    push block",
+            ".wrap",
+        );
+
+        let divisor = sys_clk_hz as f32 / TICK_HZ as f32;
+        self.slot.start(pio, &program.program, |builder| {
+            builder
+                .set_pins(pin, 1)
+                .out_pins(pin, 1)
+                .in_pin_base(pin)
+                .out_shift_direction(ShiftDirection::Right)
+                .in_shift_direction(ShiftDirection::Left)
+                .clock_divisor(divisor)
+        })?;
+
+        let result = match self.slot.task() {
+            Some(task) => transaction(&mut OneWireBus { task: task }),
+            None => Err(OneWireError::Pio(PioError::Busy)),
+        };
+
+        self.slot.stop(pio);
+        result
+    }
+}
+
+// ============================================================================
+
+impl<'a> OneWireBus<'a> {
+    /// Send an operation and wait for the sampled line level
+    fn exchange(&mut self, op: u32) -> bool {
+        while !self.task.tx.write(op) {}
+        loop {
+            if let Some(level) = self.task.rx.read() {
+                return level & 1 != 0;
+            }
+        }
+    }
+
+    /// Reset the bus, returns true if a device answered
+    pub fn reset(&mut self) -> bool {
+        !self.exchange(OP_RESET)
+    }
+
+    /// Write a bit, returns the line level sampled during the slot
+    pub fn touch_bit(&mut self, bit: bool) -> bool {
+        // The state machine takes the pin direction, 1 drives the line low
+        self.exchange(OP_SLOT | ((!bit as u32) << 1))
+    }
+
+    /// Read a bit
+    pub fn read_bit(&mut self) -> bool {
+        self.touch_bit(true)
+    }
+
+    /// Write a byte, lsb first
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.touch_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    /// Read a byte, lsb first
+    pub fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0u8, |acc, i| acc | ((self.read_bit() as u8) << i))
+    }
+
+    /// Reset the bus and select a device, or all of them
+    pub fn select(&mut self, rom: Option<&RomCode>) -> Result<(), OneWireError> {
+        if !self.reset() {
+            return Err(OneWireError::NoPresence);
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(CMD_MATCH_ROM);
+                rom.iter().for_each(|&b| self.write_byte(b));
+            },
+            None => self.write_byte(CMD_SKIP_ROM),
+        }
+        Ok(())
+    }
+
+    /// Enumerate the rom codes of the devices on the bus
+    ///
+    /// Only the devices in alarm state are listed when `alarm` is set.
+    /// Returns the number of devices found, up to the size of `roms`.
+    pub fn search(&mut self, alarm: bool, roms: &mut [RomCode]) -> Result<usize, OneWireError> {
+        let mut count = 0;
+        let mut rom: RomCode = [0; 8];
+        let mut last_discrepancy = 0;
+
+        while count < roms.len() {
+            if !self.reset() {
+                return Err(OneWireError::NoPresence);
+            }
+            self.write_byte(if alarm { CMD_ALARM_SEARCH } else { CMD_SEARCH_ROM });
+
+            let mut discrepancy = 0;
+            for bit_index in 1..=64 {
+                let byte = (bit_index - 1) / 8;
+                let mask = 1u8 << ((bit_index - 1) % 8);
+
+                let bit = self.read_bit();
+                let complement = self.read_bit();
+                let direction = match (bit, complement) {
+                    (true, true) => {
+                        // No device left on this branch
+                        return Ok(count);
+                    },
+                    (false, false) => {
+                        // Both values present, take the next branch
+                        let direction = match bit_index {
+                            i if i < last_discrepancy => rom[byte] & mask != 0,
+                            i if i == last_discrepancy => true,
+                            _ => false,
+                        };
+                        if !direction {
+                            discrepancy = bit_index;
+                        }
+                        direction
+                    },
+                    (bit, _) => bit,
+                };
+
+                match direction {
+                    true  => rom[byte] |= mask,
+                    false => rom[byte] &= !mask,
+                }
+                self.touch_bit(direction);
+            }
+
+            if crc8(&rom[0..7]) != rom[7] {
+                return Err(OneWireError::Crc);
+            }
+            roms[count] = rom;
+            count += 1;
+
+            last_discrepancy = discrepancy;
+            if last_discrepancy == 0 {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Run a DS18B20 conversion and read the temperature, in 1/16 degree
+    pub fn ds18b20_temperature(&mut self, rom: Option<&RomCode>) -> Result<i16, OneWireError> {
+        self.select(rom)?;
+        self.write_byte(CMD_CONVERT_T);
+
+        // The device holds the line low during the conversion
+        let mut slots = 0;
+        while !self.read_bit() {
+            slots += 1;
+            if slots >= CONVERSION_MAX_SLOTS {
+                return Err(OneWireError::Timeout);
+            }
+        }
+
+        self.select(rom)?;
+        self.write_byte(CMD_READ_SCRATCHPAD);
+        let mut scratchpad = [0u8; 9];
+        for b in scratchpad.iter_mut() {
+            *b = self.read_byte();
+        }
+        if crc8(&scratchpad[0..8]) != scratchpad[8] {
+            return Err(OneWireError::Crc);
+        }
+
+        Ok(i16::from_le_bytes([scratchpad[0], scratchpad[1]]))
+    }
+}
+
+// ============================================================================
+
+/// Dallas/Maxim crc8 (polynomial x^8 + x^5 + x^4 + 1)
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0x8C,
+            _ => crc >> 1,
+        })
+    })
+}
+
+// ============================================================================
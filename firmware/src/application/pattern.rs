@@ -0,0 +1,267 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::PIO1;
+use rp_pico::hal::pio::{Buffers, ShiftDirection, PIO, SM1};
+
+use super::dma_ctrl;
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Max number of steps in a pattern
+pub const PATTERN_MAX_STEPS: usize = 4096;
+
+/// Max number of pins driven by a pattern
+pub const PATTERN_MAX_PINS: u8 = 16;
+
+/// Index of the pattern state machine in pio1
+const PATTERN_SM_INDEX: usize = 1;
+
+/// Cycles spent outside the delay loop for each step
+const STEP_OVERHEAD_TICKS: u32 = 3;
+
+/// Pattern buffer, out of the stack
+static mut PATTERN_BUFFER: [u32; PATTERN_MAX_STEPS] = [0; PATTERN_MAX_STEPS];
+
+/// Address of the pattern buffer, read by the reload dma channel
+static mut PATTERN_START_ADDR: u32 = 0;
+
+// ============================================================================
+
+/// How steps are timed
+pub enum PatternTiming {
+    /// Each step lasts its own duration, in ticks of the given rate
+    Durations(u32),
+
+    /// Each step lasts one period of the given rate
+    FixedRate(u32),
+}
+
+/// Errors of the pattern generator
+pub enum PatternError {
+    /// Cannot start the state machine
+    Pio(PioError),
+
+    /// The rate cannot be reached
+    InvalidRate,
+
+    /// Steps do not fit in the buffer or no step loaded
+    InvalidSteps,
+
+    /// Too many pins
+    InvalidPins,
+}
+
+// ============================================================================
+
+/// Plays a host uploaded pattern on contiguous pins with pio and dma
+///
+/// Each step is a 32 bits word: the 16 lower bits are the pin levels (bit 0
+/// is the base pin) and the 16 upper bits the step duration in ticks.
+pub struct PatternGenerator {
+    /// State machine used to time the steps
+    slot: PioSlot<PIO1, SM1>,
+
+    /// Steps
+    buffer: &'static mut [u32; PATTERN_MAX_STEPS],
+
+    /// Number of steps loaded
+    steps: usize,
+
+    /// Remaining number of plays, None to loop forever
+    repeats: Option<u32>,
+
+    /// First pin driven
+    base: u8,
+
+    /// Number of pins driven
+    count: u8,
+}
+
+// ============================================================================
+
+impl PatternGenerator {
+    ///
+    pub fn new(slot: PioSlot<PIO1, SM1>) -> Self {
+        Self {
+            slot: slot,
+            // Safety: the buffer is only borrowed here, by the single instance
+            buffer: unsafe { &mut PATTERN_BUFFER },
+            steps: 0,
+            repeats: Some(0),
+            base: 0,
+            count: 0,
+        }
+    }
+
+    /// Load little endian steps at the given step offset
+    ///
+    /// Returns the number of steps loaded
+    pub fn load(&mut self, offset: usize, data: &[u8]) -> Result<usize, PatternError> {
+        if self.slot.is_active() {
+            return Err(PatternError::Pio(PioError::Busy));
+        }
+
+        let count = data.len() / 4;
+        if data.len() % 4 != 0 || offset > self.steps || offset + count > PATTERN_MAX_STEPS {
+            return Err(PatternError::InvalidSteps);
+        }
+
+        for (i, raw) in data.chunks_exact(4).enumerate() {
+            let step = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+
+            // Remove the loop overhead from the duration
+            let duration = (step >> 16).saturating_sub(STEP_OVERHEAD_TICKS);
+            self.buffer[offset + i] = (duration << 16) | (step & 0xFFFF);
+        }
+
+        self.steps = offset + count;
+        Ok(self.steps)
+    }
+
+    /// Start the playback, `repeats` is the number of plays (None to loop)
+    ///
+    /// Pins must already be in pio1 function mode
+    pub fn start(
+        &mut self,
+        pio: &mut PIO<PIO1>,
+        dma: &pac::DMA,
+        sys_clk_hz: u32,
+        base: u8,
+        count: u8,
+        timing: PatternTiming,
+        repeats: Option<u32>,
+    ) -> Result<(), PatternError> {
+        if self.slot.is_active() {
+            return Err(PatternError::Pio(PioError::Busy));
+        }
+        if self.steps == 0 || repeats == Some(0) {
+            return Err(PatternError::InvalidSteps);
+        }
+        if count == 0 || count > PATTERN_MAX_PINS {
+            return Err(PatternError::InvalidPins);
+        }
+
+        let timed_program = pio_proc::pio_asm!(
+            "mov osr, ~null",
+            "out pindirs, 16",
+            "out null, 16",
+            ".wrap_target",
+            "    out pins, 16",
+            "    out x, 16",
+            "delay:",
+            "    jmp x-- delay",
+            ".wrap",
+        );
+        let fixed_program = pio_proc::pio_asm!(
+            "mov osr, ~null",
+            "out pindirs, 16",
+            "out null, 16",
+            ".wrap_target",
+            "    out pins, 16",
+            "    out null, 16",
+            ".wrap",
+        );
+
+        // Fixed rate steps last 2 cycles
+        let (program, rate) = match timing {
+            PatternTiming::Durations(tick_rate) => (&timed_program.program, tick_rate),
+            PatternTiming::FixedRate(rate)      => (&fixed_program.program, rate.saturating_mul(2)),
+        };
+        if rate == 0 || rate > sys_clk_hz {
+            return Err(PatternError::InvalidRate);
+        }
+        let divisor = sys_clk_hz as f32 / rate as f32;
+        if divisor >= 65536.0 {
+            return Err(PatternError::InvalidRate);
+        }
+
+        self.slot
+            .start(pio, program, |builder| {
+                builder
+                    .out_pins(base, count)
+                    .out_shift_direction(ShiftDirection::Right)
+                    .autopull(true)
+                    .pull_threshold(32)
+                    .buffers(Buffers::OnlyTx)
+                    .clock_divisor(divisor)
+            })
+            .map_err(PatternError::Pio)?;
+
+        // Safety: read only access to the address of the tx fifo
+        let dst = unsafe { &(*pac::PIO1::ptr()).txf[PATTERN_SM_INDEX] as *const _ as u32 };
+        let dreq = dma_ctrl::DREQ_PIO1_TX0 + PATTERN_SM_INDEX as u8;
+        let steps = &self.buffer[0..self.steps];
+
+        match repeats {
+            // Chain to the reload channel to loop without gap
+            None => {
+                // Safety: only written here, while no transfer is running
+                unsafe { PATTERN_START_ADDR = steps.as_ptr() as u32 };
+                dma_ctrl::configure_reload(dma, dma_ctrl::CH_PATTERN_RELOAD, dma_ctrl::CH_PATTERN, unsafe { &PATTERN_START_ADDR });
+                dma_ctrl::start_from_memory(dma, dma_ctrl::CH_PATTERN, steps, dst, dreq, dma_ctrl::CH_PATTERN_RELOAD);
+            },
+
+            Some(_) => {
+                dma_ctrl::start_from_memory(dma, dma_ctrl::CH_PATTERN, steps, dst, dreq, dma_ctrl::CH_PATTERN);
+            },
+        }
+
+        self.repeats = repeats.map(|n| n - 1);
+        self.base = base;
+        self.count = count;
+        Ok(())
+    }
+
+    /// Stop the playback
+    pub fn stop(&mut self, pio: &mut PIO<PIO1>, dma: &pac::DMA) {
+        if self.slot.is_active() {
+            dma_ctrl::abort(dma, dma_ctrl::CH_PATTERN_RELOAD);
+            dma_ctrl::abort(dma, dma_ctrl::CH_PATTERN);
+            self.slot.stop(pio);
+        }
+        self.repeats = Some(0);
+    }
+
+    /// Restart the transfer for the remaining plays
+    pub fn update(&mut self, dma: &pac::DMA) {
+        if let Some(n) = self.repeats {
+            if n > 0 && self.slot.is_active() && !dma_ctrl::is_busy(dma, dma_ctrl::CH_PATTERN) {
+                // Safety: read only access to the address of the tx fifo
+                let dst = unsafe { &(*pac::PIO1::ptr()).txf[PATTERN_SM_INDEX] as *const _ as u32 };
+                let dreq = dma_ctrl::DREQ_PIO1_TX0 + PATTERN_SM_INDEX as u8;
+                dma_ctrl::start_from_memory(dma, dma_ctrl::CH_PATTERN, &self.buffer[0..self.steps], dst, dreq, dma_ctrl::CH_PATTERN);
+                self.repeats = Some(n - 1);
+            }
+        }
+    }
+
+    /// True while steps are played
+    pub fn is_playing(&self, dma: &pac::DMA) -> bool {
+        self.slot.is_active() && (self.repeats != Some(0) || dma_ctrl::is_busy(dma, dma_ctrl::CH_PATTERN))
+    }
+
+    /// True if the state machine drives the pins
+    pub fn is_active(&self) -> bool {
+        self.slot.is_active()
+    }
+
+    /// Remaining number of plays, None when looping
+    pub fn repeats(&self) -> Option<u32> {
+        self.repeats
+    }
+
+    /// Pins driven by the last playback, as (base, count)
+    pub fn pins(&self) -> (u8, u8) {
+        (self.base, self.count)
+    }
+
+    /// Number of steps loaded
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+// ============================================================================
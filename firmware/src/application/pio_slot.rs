@@ -0,0 +1,118 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pio::{
+    InstalledProgram, PIOBuilder, PIOExt, Running, Rx, StateMachine, StateMachineIndex, Tx,
+    UninitStateMachine, PIO,
+};
+
+// ============================================================================
+
+/// Errors when starting a pio program
+pub enum PioError {
+    /// The slot already runs a program
+    Busy,
+
+    /// Not enough space in the pio instruction memory
+    NoSpace,
+}
+
+/// Handles on a running state machine
+pub struct PioTask<P: PIOExt, SM: StateMachineIndex> {
+    pub sm: StateMachine<(P, SM), Running>,
+    pub rx: Rx<(P, SM)>,
+    pub tx: Tx<(P, SM)>,
+}
+
+/// A pio state machine that runs a program only while its feature is active
+///
+/// The program is installed when the slot is started and removed from the
+/// instruction memory when it is stopped. Features can then share the 32
+/// instructions of each pio block.
+pub enum PioSlot<P: PIOExt, SM: StateMachineIndex> {
+    /// The state machine is free
+    Idle(UninitStateMachine<(P, SM)>),
+
+    /// The state machine runs a program
+    Active(PioTask<P, SM>),
+
+    /// Only used during transitions
+    Taken,
+}
+
+// ============================================================================
+
+impl<P: PIOExt, SM: StateMachineIndex> PioSlot<P, SM> {
+    ///
+    pub fn new(sm: UninitStateMachine<(P, SM)>) -> Self {
+        Self::Idle(sm)
+    }
+
+    /// True if a program is running on the slot
+    pub fn is_active(&self) -> bool {
+        match self {
+            Self::Active(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Install the program and start the state machine
+    ///
+    /// `configure` sets up the builder (pins, clock divisor, shifts...)
+    pub fn start<F>(
+        &mut self,
+        pio: &mut PIO<P>,
+        program: &pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }>,
+        configure: F,
+    ) -> Result<(), PioError>
+    where
+        F: FnOnce(PIOBuilder<P>) -> PIOBuilder<P>,
+    {
+        let uninit = match core::mem::replace(self, Self::Taken) {
+            Self::Idle(sm) => sm,
+            other => {
+                *self = other;
+                return Err(PioError::Busy);
+            }
+        };
+
+        let installed: InstalledProgram<P> = match pio.install(program) {
+            Ok(p) => p,
+            Err(_) => {
+                *self = Self::Idle(uninit);
+                return Err(PioError::NoSpace);
+            }
+        };
+
+        let (sm, rx, tx) = configure(PIOBuilder::from_program(installed)).build(uninit);
+        *self = Self::Active(PioTask {
+            sm: sm.start(),
+            rx: rx,
+            tx: tx,
+        });
+
+        Ok(())
+    }
+
+    /// Stop the state machine and free the instruction memory
+    pub fn stop(&mut self, pio: &mut PIO<P>) {
+        match core::mem::replace(self, Self::Taken) {
+            Self::Active(task) => {
+                let (sm, program) = task.sm.uninit(task.rx, task.tx);
+                pio.uninstall(program);
+                *self = Self::Idle(sm);
+            }
+            other => *self = other,
+        }
+    }
+
+    /// Access the running state machine
+    pub fn task(&mut self) -> Option<&mut PioTask<P, SM>> {
+        match self {
+            Self::Active(task) => Some(task),
+            _ => None,
+        }
+    }
+}
+
+// ============================================================================
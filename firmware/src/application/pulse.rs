@@ -0,0 +1,95 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac::PIO0;
+use rp_pico::hal::pio::{PIO, SM0};
+
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Number of state machine cycles spent outside the high delay loop
+const HIGH_OVERHEAD_CYCLES: u32 = 3;
+
+/// Number of state machine cycles spent outside the low delay loop
+const LOW_OVERHEAD_CYCLES: u32 = 4;
+
+// ============================================================================
+
+/// Generates pulse trains on a single pin with a pio state machine
+///
+/// The state machine runs at the system clock, widths are given in cycles.
+pub struct PulseGenerator {
+    /// State machine used to time the pulses
+    slot: PioSlot<PIO0, SM0>,
+}
+
+// ============================================================================
+
+impl PulseGenerator {
+    ///
+    pub fn new(slot: PioSlot<PIO0, SM0>) -> Self {
+        Self {
+            slot: slot,
+        }
+    }
+
+    /// Check that the widths can be generated
+    pub fn is_valid(high_cycles: u32, low_cycles: u32) -> bool {
+        high_cycles >= HIGH_OVERHEAD_CYCLES && low_cycles >= LOW_OVERHEAD_CYCLES
+    }
+
+    /// Emit `count` pulses on the pin and wait for the end of the train
+    ///
+    /// The pin must already be in pio0 function mode.
+    pub fn run(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        pin: u8,
+        count: u32,
+        high_cycles: u32,
+        low_cycles: u32,
+    ) -> Result<(), PioError> {
+        let program = pio_proc::pio_asm!(
+            "set pindirs, 1",
+            "pull block",
+            "mov y, osr",        // y = number of pulses - 1
+            "pull block",
+            "mov isr, osr",      // isr = high loop count
+            "pull block",        // osr = low loop count
+            "pulse:",
+            "    set pins, 1",
+            "    mov x, isr",
+            "high:",
+            "    jmp x-- high",
+            "    set pins, 0",
+            "    mov x, osr",
+            "low:",
+            "    jmp x-- low",
+            "    jmp y-- pulse",
+            "push block",        // notify the end of the train
+            "done:",
+            "jmp done",
+        );
+
+        self.slot.start(pio, &program.program, |builder| {
+            builder
+                .set_pins(pin, 1)
+                .clock_divisor(1.0)
+        })?;
+
+        if let Some(task) = self.slot.task() {
+            task.tx.write(count - 1);
+            task.tx.write(high_cycles - HIGH_OVERHEAD_CYCLES);
+            task.tx.write(low_cycles - LOW_OVERHEAD_CYCLES);
+
+            // Wait for the end of the train
+            while task.rx.read().is_none() {}
+        }
+
+        self.slot.stop(pio);
+        Ok(())
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,184 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal;
+use rp_pico::hal::pac::PIO1;
+use rp_pico::hal::pio::{ShiftDirection, PIO, SM0};
+
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Period of the velocity computation (in us)
+const VELOCITY_PERIOD_US: u64 = 10_000;
+
+// ============================================================================
+
+/// Decodes a quadrature encoder with a pio state machine
+///
+/// The program is the quadrature encoder from the pico-examples. It tracks the
+/// raw position in its Y register and pushes it when any value is written to
+/// its TX fifo. Position offset and velocity are handled here.
+pub struct QuadratureDecoder {
+    /// State machine used to decode the phases
+    slot: PioSlot<PIO1, SM0>,
+
+    /// Pin of phase A, phase B is the next one
+    pin: u8,
+
+    /// Offset between the raw count and the reported position
+    offset: i32,
+
+    /// Raw count at the last velocity computation
+    last_raw: i32,
+
+    /// Time of the last velocity computation
+    last_time: u64,
+
+    /// Velocity in counts per second
+    velocity: i32,
+}
+
+// ============================================================================
+
+impl QuadratureDecoder {
+    ///
+    pub fn new(slot: PioSlot<PIO1, SM0>) -> Self {
+        Self {
+            slot: slot,
+            pin: 0,
+            offset: 0,
+            last_raw: 0,
+            last_time: 0,
+            velocity: 0,
+        }
+    }
+
+    /// True if the decoder runs on the pin
+    pub fn is_running_on(&self, pin: u8) -> bool {
+        self.slot.is_active() && self.pin == pin
+    }
+
+    /// Start decoding phases on `pin` (A) and `pin + 1` (B)
+    ///
+    /// The program fills 29 of the 32 instructions of pio1 from its start,
+    /// no other pio1 program can be loaded meanwhile.
+    pub fn start(&mut self, pio: &mut PIO<PIO1>, timer: &hal::Timer, pin: u8) -> Result<(), PioError> {
+        let program = pio_proc::pio_asm!(
+            ".origin 0",
+            // 00 state
+            "    jmp update",        // read 00
+            "    jmp decrement",     // read 01
+            "    jmp increment",     // read 10
+            "    jmp update",        // read 11
+            // 01 state
+            "    jmp increment",     // read 00
+            "    jmp update",        // read 01
+            "    jmp update",        // read 10
+            "    jmp decrement",     // read 11
+            // 10 state
+            "    jmp decrement",     // read 00
+            "    jmp update",        // read 01
+            "    jmp update",        // read 10
+            "    jmp increment",     // read 11
+            // 11 state
+            "    jmp update",        // read 00
+            "    jmp increment",     // read 01
+            "decrement:",
+            "    jmp y--, update",   // read 10
+            ".wrap_target",
+            "update:",
+            "    set x, 0",
+            "    pull noblock",      // osr = request or 0
+            "    mov x, osr",
+            "    mov osr, isr",
+            "    jmp !x, sample_pins",
+            "    mov isr, y",
+            "    push",
+            "sample_pins:",
+            "    mov isr, null",
+            "    in osr, 2",
+            "    in pins, 2",
+            "    mov pc, isr",
+            "increment:",
+            "    mov x, !y",
+            "    jmp x--, increment_cont",
+            "increment_cont:",
+            "    mov y, !x",
+            ".wrap",
+        );
+
+        self.slot.start(pio, &program.program, |builder| {
+            builder
+                .in_pin_base(pin)
+                .in_shift_direction(ShiftDirection::Left)
+                .clock_divisor(1.0)
+        })?;
+
+        self.pin = pin;
+        self.offset = 0;
+        self.last_raw = 0;
+        self.last_time = timer.get_counter();
+        self.velocity = 0;
+        Ok(())
+    }
+
+    /// Stop the decoder
+    pub fn stop(&mut self, pio: &mut PIO<PIO1>) {
+        self.slot.stop(pio);
+    }
+
+    /// Read the raw count of the state machine
+    fn read_raw(&mut self) -> Option<i32> {
+        self.slot.task().map(|task| {
+            task.tx.write(1);
+            loop {
+                if let Some(raw) = task.rx.read() {
+                    break raw as i32;
+                }
+            }
+        })
+    }
+
+    /// Get the current position
+    pub fn position(&mut self) -> Option<i32> {
+        let offset = self.offset;
+        self.read_raw().map(|raw| raw.wrapping_add(offset))
+    }
+
+    /// Get the velocity in counts per second
+    pub fn velocity(&self) -> i32 {
+        self.velocity
+    }
+
+    /// Set the current position
+    pub fn preset(&mut self, position: i32) -> bool {
+        match self.read_raw() {
+            Some(raw) => {
+                self.offset = position.wrapping_sub(raw);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Update the velocity computation
+    pub fn update(&mut self, timer: &hal::Timer) {
+        if !self.slot.is_active() {
+            return;
+        }
+
+        let now = timer.get_counter();
+        let elapsed = now - self.last_time;
+        if elapsed >= VELOCITY_PERIOD_US {
+            if let Some(raw) = self.read_raw() {
+                let delta = raw.wrapping_sub(self.last_raw) as i64;
+                self.velocity = (delta * 1_000_000 / elapsed as i64) as i32;
+                self.last_raw = raw;
+                self.last_time = now;
+            }
+        }
+    }
+}
+
+// ============================================================================
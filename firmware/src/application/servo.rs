@@ -0,0 +1,280 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+
+// ============================================================================
+
+/// Number of pwm slices
+const NB_SLICES: usize = 8;
+
+/// Pwm counter rate, the widths are set with a microsecond resolution
+const SERVO_TICK_HZ: u32 = 1_000_000;
+
+/// Period limits of the servo signal (in us), the counter is 16 bits
+const MIN_PERIOD_US: u32 = 2_500;
+const MAX_PERIOD_US: u32 = 65_536;
+
+/// Errors of the servo operations
+pub enum ServoError {
+    /// The pwm slice of the pin is already in use (other frequency or counter)
+    SliceBusy,
+
+    /// No servo started on this pin
+    NotStarted,
+
+    /// The frequency cannot be generated
+    InvalidFrequency,
+
+    /// The min/max widths are invalid for the frequency
+    InvalidLimits,
+
+    /// The target is out of the calibrated limits
+    OutOfRange,
+}
+
+/// Movement from one width to another
+struct ServoRamp {
+    /// Width at the start of the ramp (in us)
+    from_us: u32,
+
+    /// Start time of the ramp (in us)
+    start: u64,
+
+    /// Duration of the ramp (in us)
+    duration: u64,
+}
+
+/// State of a servo output
+struct ServoChannel {
+    /// Driven pin
+    pin: u8,
+
+    /// Calibrated limits of the pulse width (in us)
+    min_us: u32,
+    max_us: u32,
+
+    /// Angle reached at max_us (in degrees)
+    range_deg: u32,
+
+    /// Current pulse width (in us)
+    width_us: u32,
+
+    /// Target pulse width (in us)
+    target_us: u32,
+
+    /// Ramp in progress
+    ramp: Option<ServoRamp>,
+}
+
+/// State of a pwm slice used by servos
+struct ServoSlice {
+    /// Period of the signal (in us)
+    period_us: u32,
+
+    /// Channels A and B of the slice
+    channels: [Option<ServoChannel>; 2],
+}
+
+// ============================================================================
+
+/// Generates servo pulses on pwm outputs
+///
+/// Both outputs of a pwm slice share the same frequency. Ramps are computed
+/// in software, the `update` function must be called often.
+pub struct ServoOutputs {
+    /// One optional servo pair per pwm slice
+    slices: [Option<ServoSlice>; NB_SLICES],
+}
+
+// ============================================================================
+
+impl ServoOutputs {
+    ///
+    pub fn new() -> Self {
+        Self {
+            slices: [None, None, None, None, None, None, None, None],
+        }
+    }
+
+    /// Get the pwm slice and channel of a gpio
+    fn slice_index(pin: u8) -> (usize, usize) {
+        (((pin >> 1) & 7) as usize, (pin & 1) as usize)
+    }
+
+    /// True if the pwm slice is used by a servo
+    pub fn is_slice_used(&self, slice: usize) -> bool {
+        self.slices[slice].is_some()
+    }
+
+    /// Start the servo signal on the pin, at the middle of the limits
+    ///
+    /// The pin must be set in pwm function mode by the caller
+    pub fn start(
+        &mut self,
+        pwm: &pac::PWM,
+        sys_clk_hz: u32,
+        pin: u8,
+        freq_hz: u32,
+        min_us: u32,
+        max_us: u32,
+        range_deg: u32,
+    ) -> Result<(), ServoError> {
+        let period_us = match freq_hz {
+            0 => return Err(ServoError::InvalidFrequency),
+            f => SERVO_TICK_HZ / f,
+        };
+        if period_us < MIN_PERIOD_US || period_us > MAX_PERIOD_US {
+            return Err(ServoError::InvalidFrequency);
+        }
+        if min_us >= max_us || max_us >= period_us || range_deg == 0 {
+            return Err(ServoError::InvalidLimits);
+        }
+
+        let (idx, chan) = Self::slice_index(pin);
+        let ch = &pwm.ch[idx];
+        match &mut self.slices[idx] {
+            Some(slice) => {
+                if slice.period_us != period_us || slice.channels[chan].is_some() {
+                    return Err(ServoError::SliceBusy);
+                }
+            }
+            None => {
+                let div = (sys_clk_hz / SERVO_TICK_HZ) as u8;
+                ch.csr.write(|w| w.en().clear_bit());
+                ch.div.write(|w| unsafe { w.int().bits(div).frac().bits(0) });
+                ch.top.write(|w| unsafe { w.top().bits((period_us - 1) as u16) });
+                ch.cc.write(|w| unsafe { w.a().bits(0).b().bits(0) });
+                ch.ctr.write(|w| unsafe { w.ctr().bits(0) });
+                ch.csr.write(|w| w.divmode().div().en().set_bit());
+
+                self.slices[idx] = Some(ServoSlice {
+                    period_us: period_us,
+                    channels: [None, None],
+                });
+            }
+        }
+
+        let width_us = (min_us + max_us) / 2;
+        Self::write_width(pwm, pin, width_us);
+        self.slices[idx].as_mut().unwrap().channels[chan] = Some(ServoChannel {
+            pin: pin,
+            min_us: min_us,
+            max_us: max_us,
+            range_deg: range_deg,
+            width_us: width_us,
+            target_us: width_us,
+            ramp: None,
+        });
+        Ok(())
+    }
+
+    /// Stop the servo signal of the pin
+    pub fn stop(&mut self, pwm: &pac::PWM, pin: u8) -> Result<(), ServoError> {
+        self.started_channel(pin)?;
+        Self::write_width(pwm, pin, 0);
+
+        let (idx, chan) = Self::slice_index(pin);
+        let slice = self.slices[idx].as_mut().unwrap();
+        slice.channels[chan] = None;
+        if slice.channels.iter().all(|c| c.is_none()) {
+            pwm.ch[idx].csr.write(|w| w.en().clear_bit());
+            self.slices[idx] = None;
+        }
+        Ok(())
+    }
+
+    /// Convert an angle (in degrees) into a pulse width of the servo
+    pub fn angle_to_width(&mut self, pin: u8, angle_deg: u32) -> Result<u32, ServoError> {
+        let servo = self.started_channel(pin)?;
+        match angle_deg <= servo.range_deg {
+            true  => Ok(servo.min_us + (servo.max_us - servo.min_us) * angle_deg / servo.range_deg),
+            false => Err(ServoError::OutOfRange),
+        }
+    }
+
+    /// Move the servo to the width, immediately or along a ramp of
+    /// `duration_us` starting at `now`
+    pub fn set_width(
+        &mut self,
+        pwm: &pac::PWM,
+        pin: u8,
+        width_us: u32,
+        now: u64,
+        duration_us: u32,
+    ) -> Result<(), ServoError> {
+        let servo = self.started_channel(pin)?;
+        if width_us < servo.min_us || width_us > servo.max_us {
+            return Err(ServoError::OutOfRange);
+        }
+
+        servo.target_us = width_us;
+        servo.ramp = match duration_us {
+            0 => {
+                servo.width_us = width_us;
+                None
+            }
+            d => Some(ServoRamp {
+                from_us: servo.width_us,
+                start: now,
+                duration: d as u64,
+            }),
+        };
+        let width_us = servo.width_us;
+        Self::write_width(pwm, pin, width_us);
+        Ok(())
+    }
+
+    /// Get the current width of the servo and whether it is moving
+    pub fn read(&mut self, pin: u8) -> Result<(u32, bool), ServoError> {
+        let servo = self.started_channel(pin)?;
+        Ok((servo.width_us, servo.ramp.is_some()))
+    }
+
+    /// Advance the ramps in progress
+    pub fn update(&mut self, pwm: &pac::PWM, now: u64) {
+        for slot in self.slices.iter_mut() {
+            if let Some(slice) = slot {
+                for servo in slice.channels.iter_mut().flatten() {
+                    if let Some(ramp) = &servo.ramp {
+                        let elapsed = now.saturating_sub(ramp.start);
+                        if elapsed >= ramp.duration {
+                            servo.width_us = servo.target_us;
+                            servo.ramp = None;
+                        } else {
+                            let from = ramp.from_us as i64;
+                            let delta = servo.target_us as i64 - from;
+                            servo.width_us = (from + delta * elapsed as i64 / ramp.duration as i64) as u32;
+                        }
+                        Self::write_width(pwm, servo.pin, servo.width_us);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get a started servo
+    fn started_channel(&mut self, pin: u8) -> Result<&mut ServoChannel, ServoError> {
+        let (idx, chan) = Self::slice_index(pin);
+        match &mut self.slices[idx] {
+            Some(ServoSlice { channels, .. }) => match &mut channels[chan] {
+                Some(servo) if servo.pin == pin => Ok(servo),
+                _ => Err(ServoError::NotStarted),
+            },
+            None => Err(ServoError::NotStarted),
+        }
+    }
+
+    /// Set the compare value of the pin channel
+    fn write_width(pwm: &pac::PWM, pin: u8, width_us: u32) {
+        let (idx, chan) = Self::slice_index(pin);
+        pwm.ch[idx].cc.modify(|_, w| unsafe {
+            match chan {
+                0 => w.a().bits(width_us as u16),
+                _ => w.b().bits(width_us as u16),
+            }
+        });
+    }
+}
+
+// ============================================================================
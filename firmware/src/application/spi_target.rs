@@ -0,0 +1,323 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+
+// Algos
+use heapless::spsc::Queue;
+
+// ============================================================================
+
+/// Size of the response buffer
+pub const SPI_TARGET_RESPONSE_SIZE: usize = 1024;
+
+/// Size of the transaction log (in bytes)
+const LOG_SIZE: usize = 4096;
+
+/// Byte sent once the response buffer is exhausted
+const FILL_BYTE: u8 = 0xFF;
+
+/// The peripheral clock must be at least 12 times the bus clock in target
+/// mode, the prescaler is kept at its minimum
+const PRESCALER: u8 = 2;
+
+/// Log record kinds
+const LOG_SELECT: u8 = 1;
+const LOG_BYTE: u8 = 2;
+const LOG_RELEASE: u8 = 3;
+
+/// Errors of the spi target operations
+pub enum SpiTargetError {
+    /// The pin is not the RX pin of a spi block
+    InvalidPin,
+
+    /// Invalid spi mode
+    InvalidMode,
+
+    /// The target is already started
+    Busy,
+
+    /// The data does not fit in the response buffer
+    OutOfBuffer,
+}
+
+/// Configuration of the running target
+struct TargetBus {
+    /// Index of the spi block
+    block: u8,
+
+    /// First pin of the group, RX (MOSI)
+    base: u8,
+
+    /// Clock polarity and phase
+    mode: u8,
+}
+
+// ============================================================================
+
+/// Emulates a spi device
+///
+/// The response buffer is sent on MISO from its start at each transaction,
+/// then the fill byte. MOSI bytes and chip select edges are recorded in a
+/// log. The bus is polled by `update`, the chip select timestamps have the
+/// resolution of the main loop.
+pub struct SpiTarget {
+    /// First spi block
+    spi0: pac::SPI0,
+
+    /// Second spi block
+    spi1: pac::SPI1,
+
+    /// Running configuration
+    bus: Option<TargetBus>,
+
+    /// Bytes sent to the controller
+    response: [u8; SPI_TARGET_RESPONSE_SIZE],
+
+    /// Number of valid bytes in the response buffer
+    length: usize,
+
+    /// Next response byte to push in the tx fifo
+    index: usize,
+
+    /// True while the chip select is asserted
+    selected: bool,
+
+    /// Transaction log
+    log: Queue<u8, LOG_SIZE>,
+
+    /// True if log bytes have been dropped since the last fetch
+    overflow: bool,
+}
+
+// ============================================================================
+
+impl SpiTarget {
+    ///
+    pub fn new(spi0: pac::SPI0, spi1: pac::SPI1) -> Self {
+        Self {
+            spi0: spi0,
+            spi1: spi1,
+            bus: None,
+            response: [0; SPI_TARGET_RESPONSE_SIZE],
+            length: 0,
+            index: 0,
+            selected: false,
+            log: Queue::new(),
+            overflow: false,
+        }
+    }
+
+    /// Get the spi block of the first pin of a group
+    ///
+    /// The groups are RX, CSn, SCK, TX on 4 consecutive pins: spi0 from gpio
+    /// 0, 4 and 16, spi1 from gpio 8 and 12
+    pub fn group_block(base: u8) -> Result<u8, SpiTargetError> {
+        match base {
+            0 | 4 | 16 => Ok(0),
+            8 | 12     => Ok(1),
+            _ => Err(SpiTargetError::InvalidPin),
+        }
+    }
+
+    /// Registers of the spi block
+    fn regs(&self, block: u8) -> &pac::spi0::RegisterBlock {
+        match block {
+            0 => &self.spi0,
+            _ => &self.spi1,
+        }
+    }
+
+    /// Start answering on the pin group
+    ///
+    /// The pins must be set in spi function mode by the caller
+    pub fn start(&mut self, base: u8, mode: u8) -> Result<(), SpiTargetError> {
+        if self.bus.is_some() {
+            return Err(SpiTargetError::Busy);
+        }
+        let block = Self::group_block(base)?;
+        if mode > 3 {
+            return Err(SpiTargetError::InvalidMode);
+        }
+
+        self.bus = Some(TargetBus {
+            block: block,
+            base: base,
+            mode: mode,
+        });
+        while self.log.dequeue().is_some() {}
+        self.overflow = false;
+        self.selected = false;
+        self.restart();
+        Ok(())
+    }
+
+    /// Stop the target, returns the first pin of the group to release
+    pub fn stop(&mut self) -> Option<u8> {
+        let bus = self.bus.take()?;
+        self.regs(bus.block).sspcr1.write(|w| w.sse().clear_bit());
+        Some(bus.base)
+    }
+
+    /// Load bytes in the response buffer, the response ends after them
+    ///
+    /// The new response is used from the next transaction
+    pub fn load(&mut self, offset: usize, data: &[u8]) -> Result<(), SpiTargetError> {
+        let end = offset.checked_add(data.len()).ok_or(SpiTargetError::OutOfBuffer)?;
+        match self.response.get_mut(offset..end) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                self.length = end;
+                Ok(())
+            },
+            None => Err(SpiTargetError::OutOfBuffer),
+        }
+    }
+
+    /// Move the log bytes into `dest`
+    ///
+    /// Returns the number of bytes and whether bytes have been dropped
+    pub fn fetch_log(&mut self, dest: &mut [u8]) -> (usize, bool) {
+        let mut count = 0;
+        for b in dest.iter_mut() {
+            match self.log.dequeue() {
+                Some(x) => *b = x,
+                None => break,
+            }
+            count += 1;
+        }
+
+        let overflow = self.overflow;
+        self.overflow = false;
+        (count, overflow)
+    }
+
+    /// True if the target answers on the bus
+    pub fn is_active(&self) -> bool {
+        self.bus.is_some()
+    }
+
+    /// Serve the controller transactions
+    pub fn update(&mut self, now: u64) {
+        let (block, cs_pin) = match &self.bus {
+            Some(bus) => (bus.block, bus.base + 1),
+            None => return,
+        };
+
+        // Safety: read only access to the input levels
+        let cs_low = unsafe { (*pac::SIO::ptr()).gpio_in.read().bits() } & (1 << cs_pin) == 0;
+
+        // Safety: the spi blocks are owned by self, the registers are only
+        // accessed from here while the target runs
+        let spi = unsafe {
+            match block {
+                0 => &*pac::SPI0::ptr(),
+                _ => &*pac::SPI1::ptr(),
+            }
+        };
+
+        // Bytes received, the select edge may have been missed
+        while spi.sspsr.read().rne().bit_is_set() {
+            let byte = spi.sspdr.read().data().bits() as u8;
+            if !self.selected {
+                self.select(now);
+            }
+            self.record(&[LOG_BYTE, byte]);
+        }
+
+        if cs_low && !self.selected {
+            self.select(now);
+        }
+
+        if self.selected {
+            if cs_low {
+                self.feed();
+            } else {
+                self.selected = false;
+                self.record(&[LOG_RELEASE]);
+                self.record(&(now as u32).to_le_bytes());
+                self.restart();
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Record the start of a transaction
+    fn select(&mut self, now: u64) {
+        self.selected = true;
+        self.record(&[LOG_SELECT]);
+        self.record(&(now as u32).to_le_bytes());
+    }
+
+    /// Push response bytes while the tx fifo has room
+    fn feed(&mut self) {
+        let block = match &self.bus {
+            Some(bus) => bus.block,
+            None => return,
+        };
+
+        while self.regs(block).sspsr.read().tnf().bit_is_set() {
+            let byte = match self.index < self.length {
+                true  => self.response[self.index],
+                false => FILL_BYTE,
+            };
+            self.regs(block).sspdr.write(|w| unsafe { w.data().bits(byte as u16) });
+            self.index += 1;
+        }
+    }
+
+    /// Reset the block to flush the fifos and preload the response
+    fn restart(&mut self) {
+        let (block, mode) = match &self.bus {
+            Some(bus) => (bus.block, bus.mode),
+            None => return,
+        };
+
+        // Safety: only the reset bit of the owned spi block is toggled
+        let resets = unsafe { &*pac::RESETS::ptr() };
+        match block {
+            0 => {
+                resets.reset.modify(|_, w| w.spi0().set_bit());
+                resets.reset.modify(|_, w| w.spi0().clear_bit());
+                while resets.reset_done.read().spi0().bit_is_clear() {}
+            },
+            _ => {
+                resets.reset.modify(|_, w| w.spi1().set_bit());
+                resets.reset.modify(|_, w| w.spi1().clear_bit());
+                while resets.reset_done.read().spi1().bit_is_clear() {}
+            },
+        }
+
+        let spi = self.regs(block);
+        spi.sspcpsr.write(|w| unsafe { w.cpsdvsr().bits(PRESCALER) });
+        spi.sspcr0.write(|w| unsafe {
+            w.dss().bits(7)
+                .frf().bits(0)
+                .spo().bit(mode & 2 != 0)
+                .sph().bit(mode & 1 != 0)
+                .scr().bits(0)
+        });
+        spi.sspcr1.write(|w| w.ms().set_bit());
+
+        self.index = 0;
+        self.feed();
+        self.regs(block).sspcr1.write(|w| w.ms().set_bit().sse().set_bit());
+    }
+
+    /// Append a record to the log
+    ///
+    /// A record that does not fit is dropped whole, the host parser would
+    /// lose the alignment on a partial one.
+    fn record(&mut self, bytes: &[u8]) {
+        if self.log.capacity() - self.log.len() < bytes.len() {
+            self.overflow = true;
+            return;
+        }
+        for &b in bytes {
+            self.log.enqueue(b).ok();
+        }
+    }
+}
+
+// ============================================================================
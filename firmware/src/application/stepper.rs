@@ -0,0 +1,469 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::PIO1;
+use rp_pico::hal::pio::{PIO, SM2};
+
+// PIO assembler
+use pio::{Assembler, JmpCondition, MovDestination, MovOperation, MovSource, SetDestination};
+
+// Algos
+use heapless::spsc::Queue;
+
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Duration of a planned segment of constant speed (in us)
+const SEGMENT_US: u32 = 5_000;
+
+/// Number of state machine cycles spent outside each half period loop
+const HALF_PERIOD_OVERHEAD_CYCLES: u32 = 3;
+
+/// Max step rate (in steps/s)
+pub const STEPPER_MAX_SPEED: u32 = 250_000;
+
+/// Word pushed by the program when the limit input is reached
+const LIMIT_REACHED: u32 = 0xFFFF_FFFF;
+
+/// Depth of the tx fifo of the state machine
+const TX_FIFO_DEPTH: u8 = 4;
+
+/// Errors of the stepper operations
+pub enum StepperError {
+    /// Setup not done
+    NotConfigured,
+
+    /// A move is in progress
+    Busy,
+
+    /// Invalid speed or acceleration
+    InvalidSpeed,
+
+    /// Pio resource error
+    Pio(PioError),
+}
+
+/// Motion state of the stepper
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepperState {
+    Idle,
+    Moving,
+    Homing,
+}
+
+/// Pins and dynamics of the stepper
+struct StepperConfig {
+    /// Pin of the step pulses
+    step_pin: u8,
+
+    /// Pin of the direction
+    dir_pin: u8,
+
+    /// Invert the direction level
+    dir_inverted: bool,
+
+    /// Max speed (in steps/s)
+    max_speed: u32,
+
+    /// Acceleration (in steps/s^2)
+    accel: u32,
+
+    /// System clock frequency
+    sys_clk_hz: u32,
+}
+
+// ============================================================================
+
+/// Drives a stepper motor through a STEP/DIR interface
+///
+/// The cpu plans the move as segments of constant speed, the pio state
+/// machine emits the steps of each segment and reports its completion. The
+/// `update` function must be called often enough to keep the fifo filled.
+pub struct StepperDriver {
+    /// State machine used to emit the steps
+    slot: PioSlot<PIO1, SM2>,
+
+    /// Setup of the stepper
+    config: Option<StepperConfig>,
+
+    /// Motion state
+    state: StepperState,
+
+    /// Position of the motor, updated at the end of each segment
+    position: i32,
+
+    /// Position at the end of the move
+    target: i32,
+
+    /// Direction of the move (1 or -1)
+    direction: i32,
+
+    /// Number of steps of the move
+    total: u32,
+
+    /// Number of steps already sent to the state machine
+    planned: u32,
+
+    /// Constant speed of the homing move (in steps/s)
+    homing_speed: u32,
+
+    /// Step count of the segments sent and not yet completed
+    in_flight: Queue<u32, 8>,
+
+    /// True once a homing move has reached its limit
+    homed: bool,
+}
+
+// ============================================================================
+
+impl StepperDriver {
+    ///
+    pub fn new(slot: PioSlot<PIO1, SM2>) -> Self {
+        Self {
+            slot: slot,
+            config: None,
+            state: StepperState::Idle,
+            position: 0,
+            target: 0,
+            direction: 1,
+            total: 0,
+            planned: 0,
+            homing_speed: 0,
+            in_flight: Queue::new(),
+            homed: false,
+        }
+    }
+
+    /// Set the pins and dynamics, the position is reset to 0
+    ///
+    /// The step pin must be set in pio1 function mode and the dir pin as an
+    /// output by the caller
+    pub fn setup(
+        &mut self,
+        sys_clk_hz: u32,
+        step_pin: u8,
+        dir_pin: u8,
+        dir_inverted: bool,
+        max_speed: u32,
+        accel: u32,
+    ) -> Result<(), StepperError> {
+        if self.state != StepperState::Idle {
+            return Err(StepperError::Busy);
+        }
+        if max_speed == 0 || max_speed > STEPPER_MAX_SPEED || accel == 0 {
+            return Err(StepperError::InvalidSpeed);
+        }
+
+        self.config = Some(StepperConfig {
+            step_pin: step_pin,
+            dir_pin: dir_pin,
+            dir_inverted: dir_inverted,
+            max_speed: max_speed,
+            accel: accel,
+            sys_clk_hz: sys_clk_hz,
+        });
+        self.position = 0;
+        self.target = 0;
+        self.homed = false;
+        Ok(())
+    }
+
+    /// Forget the setup, returns the (step, dir) pins to release
+    pub fn release(&mut self, pio: &mut PIO<PIO1>) -> Option<(u8, u8)> {
+        self.abort(pio);
+        self.config.take().map(|c| (c.step_pin, c.dir_pin))
+    }
+
+    /// Start an acceleration limited move to the absolute position
+    pub fn move_to(&mut self, pio: &mut PIO<PIO1>, target: i32) -> Result<(), StepperError> {
+        if self.config.is_none() {
+            return Err(StepperError::NotConfigured);
+        }
+        if self.state != StepperState::Idle {
+            return Err(StepperError::Busy);
+        }
+
+        let distance = target as i64 - self.position as i64;
+        self.target = target;
+        if distance == 0 {
+            return Ok(());
+        }
+
+        self.begin(pio, distance.signum() as i32, distance.unsigned_abs() as u32, None)?;
+        self.state = StepperState::Moving;
+        Ok(())
+    }
+
+    /// Start a constant speed move towards the limit input
+    ///
+    /// The limit input must be high when reached (invert it at the pad
+    /// otherwise). The position is set to 0 at the limit.
+    pub fn home(
+        &mut self,
+        pio: &mut PIO<PIO1>,
+        limit_pin: u8,
+        speed: u32,
+        direction: i32,
+        max_steps: u32,
+    ) -> Result<(), StepperError> {
+        if self.config.is_none() {
+            return Err(StepperError::NotConfigured);
+        }
+        if self.state != StepperState::Idle {
+            return Err(StepperError::Busy);
+        }
+        if speed == 0 || speed > STEPPER_MAX_SPEED {
+            return Err(StepperError::InvalidSpeed);
+        }
+
+        self.homing_speed = speed;
+        self.homed = false;
+        self.begin(pio, direction.signum(), max_steps, Some(limit_pin))?;
+        self.state = StepperState::Homing;
+        Ok(())
+    }
+
+    /// Decelerate and stop the move in progress
+    ///
+    /// A homing move is stopped immediately.
+    pub fn stop(&mut self, pio: &mut PIO<PIO1>) {
+        match self.state {
+            StepperState::Idle => {},
+            StepperState::Homing => self.abort(pio),
+            StepperState::Moving => {
+                let config = self.config.as_ref().unwrap();
+                let speed = self.speed_at(config, self.planned, self.total - self.planned) as u64;
+                let braking = (speed * speed / (2 * config.accel as u64)) as u32;
+                self.total = self.total.min(self.planned.saturating_add(braking));
+                self.target = self.position_after(self.total);
+            },
+        }
+    }
+
+    /// Stop the steps immediately
+    ///
+    /// The steps of the segment in progress are lost, the position is no
+    /// longer reliable until the next homing.
+    pub fn abort(&mut self, pio: &mut PIO<PIO1>) {
+        if self.state != StepperState::Idle {
+            self.collect_completed();
+            self.slot.stop(pio);
+            if self.in_flight.dequeue().is_some() {
+                self.homed = false;
+            }
+            while self.in_flight.dequeue().is_some() {}
+            self.target = self.position;
+            self.state = StepperState::Idle;
+        }
+    }
+
+    /// Feed the state machine and track the position
+    pub fn update(&mut self, pio: &mut PIO<PIO1>) {
+        if self.state == StepperState::Idle {
+            return;
+        }
+
+        if self.collect_completed() {
+            // Homing limit reached
+            self.slot.stop(pio);
+            while self.in_flight.dequeue().is_some() {}
+            self.position = 0;
+            self.target = 0;
+            self.homed = true;
+            self.state = StepperState::Idle;
+            return;
+        }
+
+        self.feed();
+
+        if self.planned >= self.total && self.in_flight.is_empty() {
+            self.slot.stop(pio);
+            self.state = StepperState::Idle;
+        }
+    }
+
+    /// Get the motion state
+    pub fn state(&self) -> StepperState {
+        self.state
+    }
+
+    /// Get the position of the motor
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// Get the position at the end of the move
+    pub fn target(&self) -> i32 {
+        self.target
+    }
+
+    /// True once a homing move has reached its limit
+    pub fn is_homed(&self) -> bool {
+        self.homed
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Set the direction and start the state machine
+    fn begin(
+        &mut self,
+        pio: &mut PIO<PIO1>,
+        direction: i32,
+        total: u32,
+        limit_pin: Option<u8>,
+    ) -> Result<(), StepperError> {
+        let config = self.config.as_ref().unwrap();
+        let step_pin = config.step_pin;
+        Self::write_dir(config.dir_pin, (direction < 0) != config.dir_inverted);
+
+        // Build the program, the limit check is only used by homing moves
+        let mut a = Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
+        let mut segment = a.label();
+        let mut step = a.label();
+        let mut high = a.label();
+        let mut low = a.label();
+        let mut limit = a.label();
+        let mut halt = a.label();
+
+        a.set(SetDestination::PINDIRS, 1);
+        a.bind(&mut segment);
+        a.pull(false, true);
+        a.mov(MovDestination::Y, MovOperation::None, MovSource::OSR); // y = steps - 1
+        a.pull(false, true);                                          // osr = half period loop count
+        a.bind(&mut step);
+        if limit_pin.is_some() {
+            a.jmp(JmpCondition::PinHigh, &mut limit);
+        }
+        a.set(SetDestination::PINS, 1);
+        a.mov(MovDestination::X, MovOperation::None, MovSource::OSR);
+        a.bind(&mut high);
+        a.jmp(JmpCondition::XDecNonZero, &mut high);
+        a.set(SetDestination::PINS, 0);
+        a.mov(MovDestination::X, MovOperation::None, MovSource::OSR);
+        a.bind(&mut low);
+        a.jmp(JmpCondition::XDecNonZero, &mut low);
+        a.jmp(JmpCondition::YDecNonZero, &mut step);
+        a.push(false, false);                                         // segment done, isr is 0
+        a.jmp(JmpCondition::Always, &mut segment);
+        a.bind(&mut limit);
+        a.mov(MovDestination::ISR, MovOperation::Invert, MovSource::NULL);
+        a.push(false, true);
+        a.bind(&mut halt);
+        a.jmp(JmpCondition::Always, &mut halt);
+        let program = a.assemble_program();
+
+        self.slot
+            .start(pio, &program, |builder| {
+                builder
+                    .set_pins(step_pin, 1)
+                    .jmp_pin(limit_pin.unwrap_or(0))
+                    .clock_divisor(1.0)
+            })
+            .map_err(StepperError::Pio)?;
+
+        self.direction = direction;
+        self.total = total;
+        self.planned = 0;
+        self.feed();
+        Ok(())
+    }
+
+    /// Account the segments completed by the state machine
+    ///
+    /// Returns true if the limit input has been reached
+    fn collect_completed(&mut self) -> bool {
+        let mut limit = false;
+        if let Some(task) = self.slot.task() {
+            while let Some(word) = task.rx.read() {
+                if word == LIMIT_REACHED {
+                    limit = true;
+                    break;
+                }
+                if let Some(steps) = self.in_flight.dequeue() {
+                    self.position = self.position.wrapping_add(self.direction * steps as i32);
+                }
+            }
+        }
+        limit
+    }
+
+    /// Send the next segments while there is room in the fifo
+    fn feed(&mut self) {
+        let config = match &self.config {
+            Some(c) => c,
+            None => return,
+        };
+
+        // Safety: read only access to the fifo levels, sm2 is the stepper
+        let pio1 = unsafe { &*pac::PIO1::ptr() };
+
+        while self.planned < self.total && !self.in_flight.is_full() {
+            let level = pio1.flevel.read().tx2().bits();
+            if TX_FIFO_DEPTH - level < 2 {
+                break;
+            }
+
+            let remaining = self.total - self.planned;
+            let speed = match self.state {
+                StepperState::Homing => self.homing_speed,
+                _ => self.speed_at(config, self.planned, remaining),
+            };
+            let steps = ((speed as u64 * SEGMENT_US as u64 / 1_000_000) as u32).max(1).min(remaining);
+            let half_period = (config.sys_clk_hz / (2 * speed)).saturating_sub(HALF_PERIOD_OVERHEAD_CYCLES).max(1);
+
+            if let Some(task) = self.slot.task() {
+                task.tx.write(steps - 1);
+                task.tx.write(half_period);
+            }
+            self.in_flight.enqueue(steps).ok();
+            self.planned += steps;
+        }
+    }
+
+    /// Speed of the trapezoidal profile after `done` steps with `remaining`
+    /// steps to go
+    fn speed_at(&self, config: &StepperConfig, done: u32, remaining: u32) -> u32 {
+        let a2 = 2 * config.accel as u64;
+        let start = a2; // squared speed after the first step
+        let accel = isqrt(start + a2 * done as u64);
+        let decel = isqrt(start + a2 * remaining.saturating_sub(1) as u64);
+        accel.min(decel).min(config.max_speed as u64).max(1) as u32
+    }
+
+    /// Position reached after `steps` steps of the current move
+    fn position_after(&self, steps: u32) -> i32 {
+        let pending: u32 = self.in_flight.iter().sum();
+        let start = self.position.wrapping_sub(self.direction * (self.planned - pending) as i32);
+        start.wrapping_add(self.direction * steps as i32)
+    }
+
+    /// Drive the direction pin
+    fn write_dir(pin: u8, level: bool) {
+        // Safety: atomic set/clear of a single output bit
+        let sio = unsafe { &*pac::SIO::ptr() };
+        match level {
+            true  => sio.gpio_out_set.write(|w| unsafe { w.bits(1 << pin) }),
+            false => sio.gpio_out_clr.write(|w| unsafe { w.bits(1 << pin) }),
+        }
+    }
+}
+
+// ============================================================================
+
+/// Integer square root
+fn isqrt(x: u64) -> u64 {
+    if x < 2 {
+        return x;
+    }
+    let mut r = x;
+    let mut y = (r + 1) / 2;
+    while y < r {
+        r = y;
+        y = (r + x / r) / 2;
+    }
+    r
+}
+
+// ============================================================================
@@ -0,0 +1,129 @@
+// ============================================================================
+
+// HAL
+use rp_pico::hal::pac;
+use rp_pico::hal::pac::PIO0;
+use rp_pico::hal::pio::{ShiftDirection, PIO, SM3};
+
+use super::pio_slot::{PioError, PioSlot};
+
+// ============================================================================
+
+/// Bit rate of the led data line (in Hz)
+const WS2812_BIT_RATE_HZ: u32 = 800_000;
+
+/// Number of state machine cycles per bit (T1 + T2 + T3 of the program)
+const CYCLES_PER_BIT: u32 = 10;
+
+/// Index of the state machine in the pio registers
+const WS2812_SM_INDEX: u8 = 3;
+
+/// Time to keep the line low so that the leds latch their colors (in us)
+///
+/// Counted from the end of the last bit, the state machine is stopped once
+/// its shift register is empty.
+pub const WS2812_LATCH_US: u32 = 350;
+
+// ============================================================================
+
+/// Layout of the colors of each led
+pub enum LedFormat {
+    /// 3 bytes per led, green red blue
+    Grb,
+
+    /// 4 bytes per led, green red blue white
+    Grbw,
+}
+
+impl LedFormat {
+    /// Number of bytes of a led
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Grb  => 3,
+            Self::Grbw => 4,
+        }
+    }
+}
+
+// ============================================================================
+
+/// Drives WS2812-class addressable leds with a pio state machine
+pub struct Ws2812Driver {
+    /// State machine used to shift the colors out
+    slot: PioSlot<PIO0, SM3>,
+}
+
+// ============================================================================
+
+impl Ws2812Driver {
+    ///
+    pub fn new(slot: PioSlot<PIO0, SM3>) -> Self {
+        Self {
+            slot: slot,
+        }
+    }
+
+    /// Shift the colors out on the pin and return when the last bit is sent
+    ///
+    /// The pin must already be in pio0 function mode. The line must then stay
+    /// low for `WS2812_LATCH_US` before the leds show the new colors.
+    ///
+    /// Returns the number of leds written
+    pub fn write(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        sys_clk_hz: u32,
+        pin: u8,
+        format: LedFormat,
+        colors: &[u8],
+    ) -> Result<usize, PioError> {
+        let program = pio_proc::pio_asm!(
+            ".side_set 1",
+            "    set pindirs, 1     side 0",
+            ".wrap_target",
+            "bitloop:",
+            "    out x, 1           side 0 [2]", // T3
+            "    jmp !x do_zero     side 1 [1]", // T1
+            "    jmp bitloop        side 1 [4]", // T2, one
+            "do_zero:",
+            "    nop                side 0 [4]", // T2, zero
+            ".wrap",
+        );
+
+        let bits = (format.bytes() * 8) as u8;
+        let divisor = sys_clk_hz as f32 / (WS2812_BIT_RATE_HZ * CYCLES_PER_BIT) as f32;
+
+        self.slot.start(pio, &program.program, |builder| {
+            builder
+                .set_pins(pin, 1)
+                .side_set_pin_base(pin)
+                .out_shift_direction(ShiftDirection::Left)
+                .autopull(true)
+                .pull_threshold(bits)
+                .clock_divisor(divisor)
+        })?;
+
+        let mut count = 0;
+        if let Some(task) = self.slot.task() {
+            for led in colors.chunks_exact(format.bytes()) {
+                // Colors are sent msb first from the top of the word
+                let word = led.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32) << (32 - bits as u32);
+                while !task.tx.write(word) {}
+                count += 1;
+            }
+
+            while !task.tx.is_empty() {}
+
+            // The last word is still in the shift register: wait for the
+            // state machine to stall on the empty fifo
+            let pio0 = unsafe { &*pac::PIO0::ptr() };
+            pio0.fdebug.write(|w| unsafe { w.txstall().bits(1 << WS2812_SM_INDEX) });
+            while pio0.fdebug.read().txstall().bits() & (1 << WS2812_SM_INDEX) == 0 {}
+        }
+
+        self.slot.stop(pio);
+        Ok(count)
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,15 @@
+//! Hardware independent part of the firmware: protocol, command processing
+//! and pin backends
+//!
+//! Also built for the host, run its tests with:
+//!
+//! ```bash
+//! cargo test --lib --target x86_64-unknown-linux-gnu
+//! ```
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod processor;
+
+/// Manufacturer of the device, in the usb descriptors and the identification
+pub const MANUFACTURER_NAME: &str = "github.com/XdoctorwhoZ";
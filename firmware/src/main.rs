@@ -5,7 +5,7 @@
 use cortex_m_rt::entry;
 
 // Time handling traits
-use embedded_time::rate::*;
+use embedded_time::duration::*;
 
 // Ensure we halt the program on panic (if we don't mention this crate it won't
 // be linked)
@@ -13,6 +13,7 @@ use embedded_time::rate::*;
 
 // Pull in any important traits
 use rp_pico::hal::prelude::*;
+use embedded_hal::watchdog::WatchdogEnable;
 
 // A shorter alias for the Peripheral Access Crate, which provides low-level
 // register access
@@ -34,6 +35,9 @@ use usb_device::class_prelude::*;
 mod application;
 mod platform;
 
+use picoha_io::processor::CommandProcessor;
+use picoha_io::processor::protocol::Response;
+
 // ============================================================================
 
 /// Application object
@@ -48,6 +52,15 @@ mod platform;
 
 // ============================================================================
 
+/// Polls of the usb device before a write is given up, when the host does
+/// not read anymore
+const USB_WRITE_MAX_POLLS: u32 = 10_000;
+
+/// Size of the buffer of the json answers, with their '\n'
+const ANSWER_BUFFER_SIZE: usize = 4096;
+
+// ============================================================================
+
 /// Entry point to our bare-metal application.
 ///
 /// The `#[entry]` macro ensures the Cortex-M start-up code calls this function
@@ -88,8 +101,11 @@ fn main() -> ! {
         &mut pac.RESETS,
     ));
 
-    let mut usb_serial = platform::init_usb_serial(&usb_bus);
-    let mut usb_device = platform::init_usb_device(&usb_bus);
+    // Label and serial number of the device, from the flash
+    let identity = platform::DeviceIdentity::load();
+
+    let mut usb_classes = platform::init_usb_classes(&usb_bus);
+    let mut usb_device = platform::init_usb_device(&usb_bus, &identity);
 
     // The single-cycle I/O block controls our GPIO pins
     let sio = hal::Sio::new(pac.SIO);
@@ -102,19 +118,35 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
+    // Timer used to measure durations
+    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS);
+
     // Init. the app
     let mut app = application::PicohaIo::new(
         cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().integer()), // Append delay feature to the app
+        timer,
         pins,
+        clocks.system_clock.freq().integer(),
+        pac.PIO0,
+        pac.PIO1,
+        pac.PWM,
+        pac.DMA,
+        pac.I2C0,
+        pac.I2C1,
+        pac.SPI0,
+        pac.SPI1,
+        &mut pac.RESETS,
+        identity,
     );
 
     // Run the app
-    let mut ans_buffer = [0u8; 1024];
+    let mut ans_buffer = [0u8; ANSWER_BUFFER_SIZE];
+    let mut usb_state = platform::UsbStateTracker::new();
     loop {
         // Update USB
-        if usb_device.poll(&mut [&mut usb_serial]) {
+        if usb_classes.poll(&mut usb_device) {
             let mut buf = [0u8; 1024];
-            match usb_serial.read(&mut buf) {
+            match usb_classes.serial.read(&mut buf) {
                 Err(_) => {}
                 Ok(0)  => {}
 
@@ -124,17 +156,66 @@ fn main() -> ! {
             }
         }
 
+        // Follow the usb state, the host may be gone
+        match usb_state.update(&usb_device) {
+            Some(platform::UsbEvent::Reset)   => app.usb_reset(),
+            Some(platform::UsbEvent::Suspend) => app.usb_suspend(),
+            _ => {},
+        }
+
+        // Update the gpio reports
+        #[cfg(feature = "hid")]
+        {
+            if let Some((mask, levels)) = usb_classes.hid.read_output() {
+                app.write_outputs(mask, levels);
+            }
+            usb_classes.hid.update(app.now_us(), app.input_levels());
+        }
+
+        // Process the frames of the vendor interface
+        #[cfg(feature = "vendor")]
+        {
+            let mut payload = [0u8; platform::MAX_FRAME_PAYLOAD];
+            let frame = usb_classes.vendor.read_frame(&mut payload, app.now_us());
+            let mut send = |kind: u8, data: &[u8]| {
+                platform::usb_vendor_write_frame(&mut usb_device, &mut usb_classes, kind, data, USB_WRITE_MAX_POLLS);
+            };
+            match frame {
+                None                     => {},
+                Some(Ok((kind, size)))   => app.process_frame(kind, &payload[0..size], &mut send),
+                Some(Err(err))           => app.process_invalid_frame(err, &mut send),
+            }
+        }
+
+        // Update app background tasks
+        app.update_tasks();
+
         // Update app command process
         match app.update_command_processing() {
             None           => {},
+            Some(Response::Scpi(reply)) => {
+                platform::usb_serial_write_all(&mut usb_device, &mut usb_classes, reply.as_bytes(), USB_WRITE_MAX_POLLS);
+                platform::usb_serial_write_all(&mut usb_device, &mut usb_classes, b"\n", USB_WRITE_MAX_POLLS);
+            }
             Some(response) => {
-                match serde_json_core::to_slice(&response, &mut ans_buffer) {
-                    Ok(size) => {
-                        ans_buffer[size] = '\n' as u8;
-                        usb_serial.write(&ans_buffer[0..(size+1)]).unwrap();
-                    }
+                // Keep the last byte for the '\n'
+                let size = response.serialize(&mut ans_buffer[..ANSWER_BUFFER_SIZE - 1]);
+                ans_buffer[size] = '\n' as u8;
+                platform::usb_serial_write_all(&mut usb_device, &mut usb_classes, &ans_buffer[0..(size+1)], USB_WRITE_MAX_POLLS);
+            }
+        }
 
-                    Err(_) => {} // Ignore errors for now
+        // Reboot once the answer has reached the host
+        match app.take_reboot_request() {
+            None          => {},
+            Some(request) => {
+                platform::usb_serial_flush(&mut usb_device, &mut usb_classes, 100_000);
+                match request {
+                    application::RebootRequest::Soft => {
+                        watchdog.start(Microseconds(1_000u32));
+                        loop {}
+                    },
+                    application::RebootRequest::Bootloader => platform::reset_to_usb_boot(),
                 }
             }
         }
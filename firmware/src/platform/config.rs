@@ -1,9 +1,9 @@
 
-/// Usb Manufacturer Name
-pub const USB_MANUFACTURER_NAME: &str = "github.com/XdoctorwhoZ";
+/// Usb Manufacturer Name, shared with the host simulator
+pub const USB_MANUFACTURER_NAME: &str = picoha_io::MANUFACTURER_NAME;
 
-// Usb Product Name
-// -> USB_PRODUCT_NAME is in .cargo/config
+/// Usb Product Name, when the device has no label
+pub const USB_PRODUCT_NAME: &str = "picoha-io";
 
 /// Usb Manufacturer Id
 pub const USB_MANUFACTURER_ID: u16 = 0x16c0;
@@ -12,5 +12,11 @@ pub const USB_MANUFACTURER_ID: u16 = 0x16c0;
 pub const USB_PRODUCT_ID: u16 = 0x05e1;
 
 // Usb Serial Number
-// TEST_123456789 is the serial used for tests
-// -> USB_SERIAL_NUMBER is in .cargo/config
+// The serial stored in the config sector, or the flash unique ID in
+// hexadecimal after the prefix, see platform::identity
+
+/// Usb Serial Number prefix
+pub const USB_SERIAL_PREFIX: &str = match option_env!("USB_SERIAL_PREFIX") {
+    Some(prefix) => prefix,
+    None => "",
+};
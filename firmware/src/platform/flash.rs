@@ -0,0 +1,282 @@
+// ============================================================================
+
+// Core
+use core::ptr;
+
+// ============================================================================
+
+/// Rom addresses of the pointers to the function table and the lookup function
+const ROM_FUNC_TABLE: *const u16 = 0x0000_0014 as *const u16;
+const ROM_TABLE_LOOKUP: *const u16 = 0x0000_0018 as *const u16;
+
+/// Control register of the QSPI chip select pad
+const IO_QSPI_SS_CTRL: *mut u32 = 0x4001_800C as *mut u32;
+
+/// Chip select output override field, forced low or high
+const SS_OUTOVER_MASK: u32 = 0x3 << 8;
+const SS_OUTOVER_LOW: u32 = 0x2 << 8;
+const SS_OUTOVER_HIGH: u32 = 0x3 << 8;
+
+/// SSI status and data registers
+const SSI_SR: *const u32 = 0x1800_0028 as *const u32;
+const SSI_DR0: *mut u32 = 0x1800_0060 as *mut u32;
+
+/// SSI status flags, tx fifo not full and rx fifo not empty
+const SSI_SR_TFNF: u32 = 1 << 1;
+const SSI_SR_RFNE: u32 = 1 << 3;
+
+/// Depth of the SSI fifos
+const SSI_FIFO_DEPTH: usize = 16;
+
+/// Read unique ID command, followed by 4 dummy bytes
+const CMD_READ_UNIQUE_ID: u8 = 0x4B;
+const UNIQUE_ID_DUMMY_BYTES: usize = 4;
+
+/// Size of the flash unique ID
+pub const UNIQUE_ID_SIZE: usize = 8;
+
+/// Size of the whole read unique ID transfer
+const UNIQUE_ID_TRANSFER_SIZE: usize = 1 + UNIQUE_ID_DUMMY_BYTES + UNIQUE_ID_SIZE;
+
+/// The second stage bootloader is in the first 256 bytes of the flash
+const BOOT2_ADDRESS: *const u32 = 0x1000_0000 as *const u32;
+const BOOT2_WORDS: usize = 64;
+
+/// Address of the flash in the XIP memory map
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// Erase granularity of the flash and the matching erase command
+pub const SECTOR_SIZE: usize = 4096;
+const SECTOR_ERASE_CMD: u8 = 0x20;
+
+/// Program granularity of the flash
+pub const PAGE_SIZE: usize = 256;
+
+// ============================================================================
+
+/// Volatile accesses of the ram functions
+///
+/// Written as inline assembly, they never turn into a call to a helper in
+/// flash, whatever the optimization level. The host build only checks the
+/// code, it uses the core helpers instead.
+#[cfg(target_arch = "arm")]
+macro_rules! ram_load {
+    ($op:literal, $ty:ty, $addr:expr) => {{
+        let value: $ty;
+        core::arch::asm!(
+            concat!($op, " {v}, [{a}]"),
+            a = in(reg) $addr,
+            v = out(reg) value,
+            options(nostack, preserves_flags, readonly)
+        );
+        value
+    }};
+}
+
+#[cfg(target_arch = "arm")]
+macro_rules! ram_store {
+    ($op:literal, $addr:expr, $value:expr) => {
+        core::arch::asm!(
+            concat!($op, " {v}, [{a}]"),
+            a = in(reg) $addr,
+            v = in(reg) $value,
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+#[cfg(not(target_arch = "arm"))]
+macro_rules! ram_load {
+    ($op:literal, $ty:ty, $addr:expr) => {
+        ptr::read_volatile($addr as *const $ty)
+    };
+}
+
+#[cfg(not(target_arch = "arm"))]
+macro_rules! ram_store {
+    ($op:literal, $addr:expr, $value:expr) => {
+        ptr::write_volatile($addr, $value)
+    };
+}
+
+// ============================================================================
+
+/// Bootrom flash functions
+///
+/// The pointers are looked up while the flash is still mapped, they are
+/// called from ram once the XIP is disabled.
+struct RomFlashFunctions {
+    connect_internal_flash: extern "C" fn(),
+    flash_exit_xip: extern "C" fn(),
+    flash_range_erase: extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: extern "C" fn(),
+}
+
+impl RomFlashFunctions {
+    /// Find the functions in the bootrom table
+    fn lookup() -> Self {
+        // Safety: the bootrom functions have the C abi and these signatures
+        unsafe {
+            Self {
+                connect_internal_flash: core::mem::transmute(rom_lookup(*b"IF")),
+                flash_exit_xip: core::mem::transmute(rom_lookup(*b"EX")),
+                flash_range_erase: core::mem::transmute(rom_lookup(*b"RE")),
+                flash_range_program: core::mem::transmute(rom_lookup(*b"RP")),
+                flash_flush_cache: core::mem::transmute(rom_lookup(*b"FC")),
+            }
+        }
+    }
+}
+
+/// Get the address of a bootrom function from its 2 letters tag
+fn rom_lookup(tag: [u8; 2]) -> *const u32 {
+    // Safety: the rom table pointers are 16 bits addresses at fixed places
+    unsafe {
+        let lookup: extern "C" fn(*const u16, u32) -> *const u32 =
+            core::mem::transmute(ptr::read_volatile(ROM_TABLE_LOOKUP) as usize);
+        let table = ptr::read_volatile(ROM_FUNC_TABLE) as usize as *const u16;
+        lookup(table, u16::from_le_bytes(tag) as u32)
+    }
+}
+
+/// Copy the second stage bootloader in ram
+///
+/// It restores the fast XIP mode after a flash operation
+fn boot2_copy() -> [u32; BOOT2_WORDS] {
+    let mut boot2 = [0u32; BOOT2_WORDS];
+    for (i, word) in boot2.iter_mut().enumerate() {
+        // Safety: the boot2 area is always mapped before the operation
+        *word = unsafe { ptr::read_volatile(BOOT2_ADDRESS.add(i)) };
+    }
+    boot2
+}
+
+// ============================================================================
+
+/// Read the 64 bits unique ID of the flash chip
+///
+/// The XIP is suspended during the transfer, interrupts are disabled and the
+/// code runs from ram. Must not be called while the second core executes
+/// from flash.
+pub fn read_unique_id() -> [u8; UNIQUE_ID_SIZE] {
+    let rom = RomFlashFunctions::lookup();
+    let boot2 = boot2_copy();
+
+    let mut tx = [0u8; UNIQUE_ID_TRANSFER_SIZE];
+    let mut rx = [0u8; UNIQUE_ID_TRANSFER_SIZE];
+    tx[0] = CMD_READ_UNIQUE_ID;
+
+    cortex_m::interrupt::free(|_| {
+        // Safety: the transfer function only touches the flash interface
+        unsafe {
+            flash_transfer(&rom, boot2.as_ptr(), tx.as_ptr(), rx.as_mut_ptr(), UNIQUE_ID_TRANSFER_SIZE)
+        };
+    });
+
+    let mut id = [0u8; UNIQUE_ID_SIZE];
+    id.copy_from_slice(&rx[1 + UNIQUE_ID_DUMMY_BYTES..]);
+    id
+}
+
+/// Read bytes of the flash through the XIP
+///
+/// `offset` is relative to the start of the flash
+pub fn read(offset: u32, dest: &mut [u8]) {
+    for (i, b) in dest.iter_mut().enumerate() {
+        // Safety: the flash is mapped read only at the XIP base
+        *b = unsafe { ptr::read_volatile((XIP_BASE + offset + i as u32) as *const u8) };
+    }
+}
+
+/// Erase a sector and program its first page
+///
+/// `offset` is relative to the start of the flash and must be aligned on a
+/// sector. The same constraints as `read_unique_id` apply, and the sector
+/// must not hold code.
+pub fn write_sector_page(offset: u32, page: &[u8; PAGE_SIZE]) {
+    let rom = RomFlashFunctions::lookup();
+    let boot2 = boot2_copy();
+
+    // The rom reads the data while the XIP is disabled, it must be in ram
+    let data = *page;
+
+    cortex_m::interrupt::free(|_| {
+        // Safety: the caller keeps the sector away from the program
+        unsafe { flash_erase_program(&rom, boot2.as_ptr(), offset, data.as_ptr()) };
+    });
+}
+
+/// Run a raw spi transfer on the flash chip
+///
+/// Nothing in here may live in flash: the registers and the buffers are only
+/// accessed with `ram_load` and `ram_store`, no function call except the
+/// bootrom ones.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn flash_transfer(
+    rom: &RomFlashFunctions,
+    boot2: *const u32,
+    tx: *const u8,
+    rx: *mut u8,
+    count: usize,
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+
+    let ss = ram_load!("ldr", u32, IO_QSPI_SS_CTRL) & !SS_OUTOVER_MASK;
+    ram_store!("str", IO_QSPI_SS_CTRL, ss | SS_OUTOVER_LOW);
+
+    // Never push more bytes than the rx fifo can hold
+    let mut tx_remaining = count;
+    let mut rx_remaining = count;
+    while tx_remaining > 0 || rx_remaining > 0 {
+        let status = ram_load!("ldr", u32, SSI_SR);
+        if status & SSI_SR_TFNF != 0
+            && tx_remaining > 0
+            && rx_remaining - tx_remaining < SSI_FIFO_DEPTH - 2
+        {
+            let byte = ram_load!("ldrb", u8, (tx as usize + count - tx_remaining) as *const u8);
+            ram_store!("str", SSI_DR0, byte as u32);
+            tx_remaining -= 1;
+        }
+        if status & SSI_SR_RFNE != 0 && rx_remaining > 0 {
+            let byte = ram_load!("ldr", u32, SSI_DR0 as *const u32) as u8;
+            ram_store!("strb", (rx as usize + count - rx_remaining) as *mut u8, byte);
+            rx_remaining -= 1;
+        }
+    }
+
+    ram_store!("str", IO_QSPI_SS_CTRL, ss | SS_OUTOVER_HIGH);
+
+    (rom.flash_flush_cache)();
+
+    // Thumb code, the lowest bit of the address must be set
+    let boot2_entry: extern "C" fn() = core::mem::transmute(boot2 as usize | 1);
+    boot2_entry();
+}
+
+/// Erase a sector and program a page at its start
+///
+/// Same constraints as `flash_transfer`.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn flash_erase_program(
+    rom: &RomFlashFunctions,
+    boot2: *const u32,
+    offset: u32,
+    data: *const u8,
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+
+    (rom.flash_range_erase)(offset, SECTOR_SIZE, SECTOR_SIZE as u32, SECTOR_ERASE_CMD);
+    (rom.flash_range_program)(offset, data, PAGE_SIZE);
+
+    (rom.flash_flush_cache)();
+
+    let boot2_entry: extern "C" fn() = core::mem::transmute(boot2 as usize | 1);
+    boot2_entry();
+}
+
+// ============================================================================
@@ -0,0 +1,99 @@
+// ============================================================================
+
+// USB crates
+use rp_pico::hal::usb::UsbBus;
+use usb_device::class_prelude::{UsbBusAllocator, UsbClass};
+
+// USB Human Interface Device support
+use usbd_hid::hid_class::HIDClass;
+
+// ============================================================================
+
+/// Interval of the input reports (in ms), also the endpoint polling interval
+pub const HID_REPORT_INTERVAL_MS: u8 = 1;
+
+/// Size of the input and output reports
+pub const HID_REPORT_SIZE: usize = 8;
+
+/// Vendor defined report descriptor, one 8 bytes input report and one 8
+/// bytes output report, without report id
+///
+/// - input: gpio levels (u32, bit X => gpioX), then the timestamp of the
+///   sample (u32, in us), both little endian
+/// - output: mask of the gpios to write (u32), then their levels (u32), both
+///   little endian
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF,   // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01,         // Usage (0x01)
+    0xA1, 0x01,         // Collection (Application)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+    0x75, 0x08,         //   Report Size (8)
+    0x95, 0x08,         //   Report Count (8)
+    0x09, 0x02,         //   Usage (0x02)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    0x95, 0x08,         //   Report Count (8)
+    0x09, 0x03,         //   Usage (0x03)
+    0x91, 0x02,         //   Output (Data, Variable, Absolute)
+    0xC0,               // End Collection
+];
+
+// ============================================================================
+
+/// Gpio reports on a HID interface
+///
+/// The host reads the input levels without polling a command, and sets
+/// outputs without tty permissions.
+pub struct GpioHid<'a> {
+    /// HID interface with its interrupt endpoints
+    class: HIDClass<'a, UsbBus>,
+
+    /// Time of the last input report (in us)
+    last_report: u64,
+}
+
+// ============================================================================
+
+impl<'a> GpioHid<'a> {
+    ///
+    pub fn new(usb_bus: &'a UsbBusAllocator<UsbBus>) -> Self {
+        Self {
+            class: HIDClass::new(usb_bus, REPORT_DESCRIPTOR, HID_REPORT_INTERVAL_MS),
+            last_report: 0,
+        }
+    }
+
+    /// The usb class to poll
+    pub fn class(&mut self) -> &mut dyn UsbClass<UsbBus> {
+        &mut self.class
+    }
+
+    /// Get the output write requested by the host, as (mask, levels)
+    pub fn read_output(&mut self) -> Option<(u32, u32)> {
+        let mut report = [0u8; HID_REPORT_SIZE];
+        match self.class.pull_raw_output(&mut report) {
+            Ok(HID_REPORT_SIZE) => Some((
+                u32::from_le_bytes([report[0], report[1], report[2], report[3]]),
+                u32::from_le_bytes([report[4], report[5], report[6], report[7]]),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Send the input levels once the report interval has elapsed
+    ///
+    /// A report is dropped when the host has not read the previous one.
+    pub fn update(&mut self, now: u64, levels: u32) {
+        if now.wrapping_sub(self.last_report) < HID_REPORT_INTERVAL_MS as u64 * 1000 {
+            return;
+        }
+        self.last_report = now;
+
+        let mut report = [0u8; HID_REPORT_SIZE];
+        report[0..4].copy_from_slice(&levels.to_le_bytes());
+        report[4..8].copy_from_slice(&(now as u32).to_le_bytes());
+        self.class.push_raw_input(&report).ok();
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,162 @@
+// ============================================================================
+
+// Core
+use core::fmt::Write;
+use core::str::FromStr;
+
+// Algos
+use heapless::String;
+
+use super::config;
+use super::flash;
+
+// ============================================================================
+
+/// Max size of the label and of the serial number
+pub const IDENTITY_FIELD_SIZE: usize = 32;
+
+/// Max size of the usb serial number, a longer prefix is ignored
+pub const USB_SERIAL_NUMBER_SIZE: usize = 64;
+
+/// Offset of the config sector, the last sector of the 2MB flash
+///
+/// The sector is removed from the program area in memory.x
+const CONFIG_SECTOR_OFFSET: u32 = 0x1F_F000;
+
+/// Marker of a valid config page, "PHID"
+const CONFIG_MAGIC: [u8; 4] = *b"PHID";
+
+/// Layout version of the config page
+const CONFIG_VERSION: u8 = 1;
+
+/// Offsets in the config page: magic, version, label and serial lengths,
+/// then both fields
+const OFFSET_VERSION: usize = 4;
+const OFFSET_LABEL_LEN: usize = 5;
+const OFFSET_SERIAL_LEN: usize = 6;
+const OFFSET_LABEL: usize = 8;
+const OFFSET_SERIAL: usize = OFFSET_LABEL + IDENTITY_FIELD_SIZE;
+
+/// Errors of the identity fields
+pub enum IdentityError {
+    /// The value does not fit in the field
+    TooLong,
+
+    /// The value has characters not allowed in the field
+    InvalidChar,
+}
+
+// ============================================================================
+
+/// Names given to the device by the user, kept in the config sector
+///
+/// Empty fields fall back on the defaults: the product name of the firmware
+/// for the label and the flash unique ID for the serial number.
+pub struct DeviceIdentity {
+    /// User label, reported as usb product name
+    label: String<IDENTITY_FIELD_SIZE>,
+
+    /// User serial number, reported as usb serial number
+    serial: String<IDENTITY_FIELD_SIZE>,
+
+    /// Unique ID of the flash chip
+    unique_id: [u8; flash::UNIQUE_ID_SIZE],
+}
+
+// ============================================================================
+
+impl DeviceIdentity {
+    /// Read the identity from the config sector and the flash chip
+    pub fn load() -> Self {
+        let mut identity = Self {
+            label: String::new(),
+            serial: String::new(),
+            unique_id: flash::read_unique_id(),
+        };
+
+        let mut page = [0u8; flash::PAGE_SIZE];
+        flash::read(CONFIG_SECTOR_OFFSET, &mut page);
+        if page[0..4] != CONFIG_MAGIC || page[OFFSET_VERSION] != CONFIG_VERSION {
+            return identity;
+        }
+
+        // Invalid fields are ignored, the defaults are used instead
+        let label_len = page[OFFSET_LABEL_LEN] as usize;
+        if label_len <= IDENTITY_FIELD_SIZE {
+            if let Ok(label) = core::str::from_utf8(&page[OFFSET_LABEL..OFFSET_LABEL + label_len]) {
+                identity.set_label(label).ok();
+            }
+        }
+        let serial_len = page[OFFSET_SERIAL_LEN] as usize;
+        if serial_len <= IDENTITY_FIELD_SIZE {
+            if let Ok(serial) = core::str::from_utf8(&page[OFFSET_SERIAL..OFFSET_SERIAL + serial_len]) {
+                identity.set_serial(serial).ok();
+            }
+        }
+        identity
+    }
+
+    /// Write the identity in the config sector
+    ///
+    /// Interrupts are disabled for the duration of the sector erase (about
+    /// 50 ms).
+    pub fn store(&self) {
+        let mut page = [0xFFu8; flash::PAGE_SIZE];
+        page[0..4].copy_from_slice(&CONFIG_MAGIC);
+        page[OFFSET_VERSION] = CONFIG_VERSION;
+        page[OFFSET_LABEL_LEN] = self.label.len() as u8;
+        page[OFFSET_SERIAL_LEN] = self.serial.len() as u8;
+        page[OFFSET_LABEL..OFFSET_LABEL + self.label.len()].copy_from_slice(self.label.as_bytes());
+        page[OFFSET_SERIAL..OFFSET_SERIAL + self.serial.len()].copy_from_slice(self.serial.as_bytes());
+        flash::write_sector_page(CONFIG_SECTOR_OFFSET, &page);
+    }
+
+    /// User label, empty if not set
+    pub fn label(&self) -> &str {
+        self.label.as_str()
+    }
+
+    /// Set the user label, any printable ascii character is allowed
+    pub fn set_label(&mut self, label: &str) -> Result<(), IdentityError> {
+        if !label.bytes().all(|c| c.is_ascii_graphic() || c == b' ') {
+            return Err(IdentityError::InvalidChar);
+        }
+        self.label = String::from_str(label).map_err(|_| IdentityError::TooLong)?;
+        Ok(())
+    }
+
+    /// Set the user serial number, letters, digits, '-', '_' and '.' only
+    pub fn set_serial(&mut self, serial: &str) -> Result<(), IdentityError> {
+        if !serial.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.') {
+            return Err(IdentityError::InvalidChar);
+        }
+        self.serial = String::from_str(serial).map_err(|_| IdentityError::TooLong)?;
+        Ok(())
+    }
+
+    /// Name reported as usb product: the label or the firmware product name
+    pub fn usb_product_name(&self) -> &str {
+        match self.label.is_empty() {
+            true  => config::USB_PRODUCT_NAME,
+            false => self.label.as_str(),
+        }
+    }
+
+    /// Serial number reported on usb: the user serial, or the flash unique
+    /// ID in hexadecimal after the build time prefix
+    pub fn usb_serial_number(&self) -> String<USB_SERIAL_NUMBER_SIZE> {
+        let mut serial = String::new();
+        if !self.serial.is_empty() {
+            serial.push_str(self.serial.as_str()).ok();
+            return serial;
+        }
+
+        serial.push_str(config::USB_SERIAL_PREFIX).ok();
+        for byte in self.unique_id {
+            write!(serial, "{:02X}", byte).ok();
+        }
+        serial
+    }
+}
+
+// ============================================================================
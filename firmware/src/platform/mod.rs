@@ -1,41 +1,275 @@
 // ============================================================================
 
+// Algos
+use heapless::{String, Vec};
+
 // USB crates
 use rp_pico::hal::usb::UsbBus;
-use usb_device::class_prelude::UsbBusAllocator;
+use usb_device::class_prelude::{UsbBusAllocator, UsbClass};
 use usb_device::prelude::UsbDevice;
 use usb_device::prelude::UsbDeviceBuilder;
+use usb_device::prelude::UsbDeviceState;
 use usb_device::prelude::UsbVidPid;
+use usb_device::UsbError;
+
+// ROM functions
+use rp_pico::hal::rom_data;
 
 // USB Communications Class Device support
 use usbd_serial::SerialPort;
+#[cfg(not(any(feature = "hid", feature = "vendor")))]
 use usbd_serial::USB_CLASS_CDC;
 
 // ============================================================================
 
 mod config;
+pub use config::USB_MANUFACTURER_NAME;
+mod flash;
+
+mod identity;
+pub use identity::{DeviceIdentity, IdentityError};
+use identity::{IDENTITY_FIELD_SIZE, USB_SERIAL_NUMBER_SIZE};
+
+#[cfg(feature = "hid")]
+mod hid;
+#[cfg(feature = "hid")]
+pub use hid::GpioHid;
+
+#[cfg(feature = "vendor")]
+mod vendor;
+#[cfg(feature = "vendor")]
+pub use vendor::VendorBulk;
+#[cfg(feature = "vendor")]
+pub use picoha_io::processor::frame::MAX_FRAME_PAYLOAD;
+
+// ============================================================================
+
+/// Max number of usb classes on the device
+const MAX_USB_CLASSES: usize = 3;
+
+/// Device class, subclass and protocol
+///
+/// A composite device announces the interface associations of its classes.
+#[cfg(not(any(feature = "hid", feature = "vendor")))]
+const USB_DEVICE_CLASS: (u8, u8, u8) = (USB_CLASS_CDC, 0x00, 0x00);
+#[cfg(any(feature = "hid", feature = "vendor"))]
+const USB_DEVICE_CLASS: (u8, u8, u8) = (0xEF, 0x02, 0x01);
+
+/// Usb string descriptors, they must live as long as the usb device
+static mut USB_PRODUCT_NAME: String<IDENTITY_FIELD_SIZE> = String::new();
+static mut USB_SERIAL_NUMBER: String<USB_SERIAL_NUMBER_SIZE> = String::new();
 
 // ============================================================================
 
 /// Create a USB device with a fake VID and PID
-pub fn init_usb_device(usb_bus: &UsbBusAllocator<UsbBus>) -> UsbDevice<UsbBus> {
+///
+/// The product name and the serial number come from the identity of the
+/// device, changes of the identity are applied at the next boot.
+pub fn init_usb_device<'a>(
+    usb_bus: &'a UsbBusAllocator<UsbBus>,
+    identity: &DeviceIdentity,
+) -> UsbDevice<'a, UsbBus> {
+    // Safety: only written here, before the usb device is created
+    let (product, serial) = unsafe {
+        USB_PRODUCT_NAME.clear();
+        USB_PRODUCT_NAME.push_str(identity.usb_product_name()).ok();
+        USB_SERIAL_NUMBER = identity.usb_serial_number();
+        (USB_PRODUCT_NAME.as_str(), USB_SERIAL_NUMBER.as_str())
+    };
+
     UsbDeviceBuilder::new(
         &usb_bus,
         UsbVidPid(config::USB_MANUFACTURER_ID, config::USB_PRODUCT_ID),
     )
     .manufacturer(config::USB_MANUFACTURER_NAME)
-    .product(env!("USB_PRODUCT_NAME"))
-    //.serial_number(config::USB_SERIAL_NUMBER)
-    .serial_number(env!("USB_SERIAL_NUMBER"))
-    .device_class(USB_CLASS_CDC) // from: https://www.usb.org/defined-class-codes
+    .product(product)
+    .serial_number(serial)
+    .device_class(USB_DEVICE_CLASS.0) // from: https://www.usb.org/defined-class-codes
+    .device_sub_class(USB_DEVICE_CLASS.1)
+    .device_protocol(USB_DEVICE_CLASS.2)
     .build()
 }
 
 // ============================================================================
 
-/// Intialize the usb device object
-pub fn init_usb_serial(usb_bus: &UsbBusAllocator<UsbBus>) -> SerialPort<UsbBus> {
-    return SerialPort::new(&usb_bus);
+/// Usb interfaces of the device
+///
+/// All of them must be given to each poll of the device, the other ones
+/// would miss their events.
+pub struct UsbClasses<'a> {
+    /// Command port
+    pub serial: SerialPort<'a, UsbBus>,
+
+    /// Gpio reports
+    #[cfg(feature = "hid")]
+    pub hid: GpioHid<'a>,
+
+    /// Framed protocol and bulk data
+    #[cfg(feature = "vendor")]
+    pub vendor: VendorBulk<'a>,
+}
+
+impl<'a> UsbClasses<'a> {
+    /// Poll the device with every class, true if a class may have data
+    pub fn poll(&mut self, usb_device: &mut UsbDevice<'a, UsbBus>) -> bool {
+        let mut classes: Vec<&mut dyn UsbClass<UsbBus>, MAX_USB_CLASSES> = Vec::new();
+        classes.push(&mut self.serial).ok();
+        #[cfg(feature = "hid")]
+        classes.push(self.hid.class()).ok();
+        #[cfg(feature = "vendor")]
+        classes.push(&mut self.vendor).ok();
+
+        usb_device.poll(&mut classes)
+    }
+}
+
+/// Intialize the usb interfaces, before the usb device
+pub fn init_usb_classes(usb_bus: &UsbBusAllocator<UsbBus>) -> UsbClasses<'_> {
+    UsbClasses {
+        serial: SerialPort::new(&usb_bus),
+        #[cfg(feature = "hid")]
+        hid: GpioHid::new(&usb_bus),
+        #[cfg(feature = "vendor")]
+        vendor: VendorBulk::new(&usb_bus),
+    }
+}
+
+// ============================================================================
+
+/// Transitions of the usb device state
+pub enum UsbEvent {
+    /// Bus reset, also seen when the cable is unplugged
+    Reset,
+
+    /// The host suspended the bus
+    Suspend,
+
+    /// The bus activity restarted after a suspend
+    Resume,
+
+    /// The host selected the configuration
+    Configured,
+}
+
+/// Follows the state of the usb device
+///
+/// The state is sampled, the transitions that happen between two updates
+/// are merged.
+pub struct UsbStateTracker {
+    /// State at the last update
+    state: UsbDeviceState,
+}
+
+impl UsbStateTracker {
+    ///
+    pub fn new() -> Self {
+        Self {
+            state: UsbDeviceState::Default,
+        }
+    }
+
+    /// Get the transition since the last update
+    ///
+    /// The usb classes reset their own buffers on a bus reset.
+    pub fn update(&mut self, usb_device: &UsbDevice<UsbBus>) -> Option<UsbEvent> {
+        let state = usb_device.state();
+        if state == self.state {
+            return None;
+        }
+
+        let previous = core::mem::replace(&mut self.state, state);
+        match (previous, state) {
+            (_, UsbDeviceState::Suspend)                            => Some(UsbEvent::Suspend),
+            (_, UsbDeviceState::Default)                            => Some(UsbEvent::Reset),
+            (UsbDeviceState::Configured, UsbDeviceState::Addressed) => Some(UsbEvent::Reset),
+            (UsbDeviceState::Suspend, _)                            => Some(UsbEvent::Resume),
+            (_, UsbDeviceState::Configured)                         => Some(UsbEvent::Configured),
+            _ => None,
+        }
+    }
+}
+
+// ============================================================================
+
+/// Write the whole data on the usb serial
+///
+/// The serial port only buffers a few bytes, the device is polled until
+/// everything has been sent. Gives up after `max_polls` polls of the device,
+/// when the host does not read the port: the rest of the data is dropped.
+pub fn usb_serial_write_all<'a>(
+    usb_device: &mut UsbDevice<'a, UsbBus>,
+    usb_classes: &mut UsbClasses<'a>,
+    data: &[u8],
+    max_polls: u32,
+) {
+    let mut offset = 0;
+    let mut polls = 0;
+    while offset < data.len() && polls < max_polls {
+        match usb_classes.serial.write(&data[offset..]) {
+            Ok(count) => offset += count,
+            Err(UsbError::WouldBlock) => {
+                usb_classes.poll(usb_device);
+                polls += 1;
+            },
+            Err(_) => break, // Ignore errors for now
+        }
+    }
+}
+
+// ============================================================================
+
+/// Wait for the data written on the usb serial to be sent to the host
+///
+/// Gives up after `max_polls` polls of the device, when the host does not
+/// read the port.
+pub fn usb_serial_flush<'a>(
+    usb_device: &mut UsbDevice<'a, UsbBus>,
+    usb_classes: &mut UsbClasses<'a>,
+    max_polls: u32,
+) {
+    for _ in 0..max_polls {
+        match usb_classes.serial.flush() {
+            Err(UsbError::WouldBlock) => {
+                usb_classes.poll(usb_device);
+            },
+            _ => break,
+        }
+    }
+}
+
+// ============================================================================
+
+/// Send a whole frame on the vendor interface
+///
+/// The device is polled until the previous frame has been sent. Gives up
+/// after `max_polls` polls of the device, when the host does not read the
+/// interface: the frame is dropped.
+#[cfg(feature = "vendor")]
+pub fn usb_vendor_write_frame<'a>(
+    usb_device: &mut UsbDevice<'a, UsbBus>,
+    usb_classes: &mut UsbClasses<'a>,
+    kind: u8,
+    payload: &[u8],
+    max_polls: u32,
+) {
+    for _ in 0..max_polls {
+        match usb_classes.vendor.write_frame(kind, payload) {
+            Err(UsbError::WouldBlock) => {
+                usb_classes.poll(usb_device);
+            },
+            _ => break, // Ignore errors for now
+        }
+    }
+}
+
+// ============================================================================
+
+/// Restart in the rom usb bootloader, the device enumerates as an UF2 mass
+/// storage (same as a reset with BOOTSEL pressed)
+pub fn reset_to_usb_boot() -> ! {
+    // Blink the board led on usb activity, keep all the interfaces
+    rom_data::reset_to_usb_boot(1 << 25, 0);
+    loop {}
 }
 
 // ============================================================================
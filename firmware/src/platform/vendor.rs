@@ -0,0 +1,226 @@
+// ============================================================================
+
+// USB crates
+use rp_pico::hal::usb::UsbBus;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+use usb_device::UsbError;
+
+// Framing
+use picoha_io::processor::frame::{FrameError, FrameReader, FRAME_HEADER_SIZE, MAX_FRAME_PAYLOAD};
+
+// ============================================================================
+
+/// Size of the bulk packets at full speed
+const PACKET_SIZE: usize = 64;
+
+/// Size of the frame buffers
+const FRAME_BUFFER_SIZE: usize = FRAME_HEADER_SIZE + MAX_FRAME_PAYLOAD;
+
+/// Vendor interface class
+const USB_CLASS_VENDOR: u8 = 0xFF;
+
+/// Bos platform capability type
+const CAPABILITY_PLATFORM: u8 = 0x05;
+
+/// Vendor request code of the MS OS 2.0 descriptor set, and its index
+const MS_OS_VENDOR_CODE: u8 = 0x01;
+const MS_OS_DESCRIPTOR_INDEX: u16 = 0x07;
+
+/// Size of the MS OS 2.0 descriptor set: set header, configuration subset,
+/// function subset and compatible ID
+const MS_OS_SET_SIZE: usize = 10 + 8 + 8 + 20;
+
+/// MS OS 2.0 platform capability, for Windows 8.1 and later
+const MS_OS_PLATFORM_CAPABILITY: [u8; 25] = [
+    0x00,                                           // bReserved
+    0xDF, 0x60, 0xDD, 0xD8, 0x89, 0x45, 0xC7, 0x4C, // MS OS 2.0 platform UUID
+    0x9C, 0xD2, 0x65, 0x9D, 0x9E, 0x64, 0x8A, 0x9F, //   D8DD60DF-4589-4CC7-9CD2-659D9E648A9F
+    0x00, 0x00, 0x03, 0x06,                         // dwWindowsVersion
+    MS_OS_SET_SIZE as u8, 0x00,                     // wMSOSDescriptorSetTotalLength
+    MS_OS_VENDOR_CODE,                              // bMS_VendorCode
+    0x00,                                           // bAltEnumCode
+];
+
+// ============================================================================
+
+/// Vendor specific interface with a pair of bulk endpoints
+///
+/// Windows binds the WinUSB driver from the MS OS 2.0 descriptors, libusb
+/// finds the interface from its class. Both directions carry frames: a 4
+/// bytes header (kind, flags, payload length) then the payload.
+pub struct VendorBulk<'a> {
+    /// Interface number
+    interface: InterfaceNumber,
+
+    /// Host to device endpoint
+    read_ep: EndpointOut<'a, UsbBus>,
+
+    /// Device to host endpoint
+    write_ep: EndpointIn<'a, UsbBus>,
+
+    /// MS OS 2.0 descriptor set, binds the interface to WinUSB
+    ms_os_set: [u8; MS_OS_SET_SIZE],
+
+    /// Bytes received, a whole frame and the start of the next one
+    rx: FrameReader<{ FRAME_BUFFER_SIZE + PACKET_SIZE }>,
+
+    /// Time given to the last frame read, dates the packets read by the polls
+    now_us: u64,
+
+    /// Frame being sent
+    tx: [u8; FRAME_BUFFER_SIZE],
+    tx_len: usize,
+    tx_pos: usize,
+
+    /// The frame ends on a full packet, a zero length packet must follow
+    tx_zlp: bool,
+}
+
+// ============================================================================
+
+impl<'a> VendorBulk<'a> {
+    ///
+    pub fn new(usb_bus: &'a UsbBusAllocator<UsbBus>) -> Self {
+        let interface = usb_bus.interface();
+        let if_num: u8 = interface.into();
+
+        let ms_os_set = [
+            // Set header: windows version, total length
+            0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06, MS_OS_SET_SIZE as u8, 0x00,
+            // Configuration subset header: configuration 0, subset length
+            0x08, 0x00, 0x01, 0x00, 0x00, 0x00, (MS_OS_SET_SIZE - 10) as u8, 0x00,
+            // Function subset header: first interface, subset length
+            0x08, 0x00, 0x02, 0x00, if_num, 0x00, (MS_OS_SET_SIZE - 18) as u8, 0x00,
+            // Compatible ID: "WINUSB", no sub-compatible ID
+            0x14, 0x00, 0x03, 0x00,
+            b'W', b'I', b'N', b'U', b'S', b'B', 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        Self {
+            interface: interface,
+            read_ep: usb_bus.bulk(PACKET_SIZE as u16),
+            write_ep: usb_bus.bulk(PACKET_SIZE as u16),
+            ms_os_set: ms_os_set,
+            rx: FrameReader::new(),
+            now_us: 0,
+            tx: [0; FRAME_BUFFER_SIZE],
+            tx_len: 0,
+            tx_pos: 0,
+            tx_zlp: false,
+        }
+    }
+
+    /// Get the next frame received from the host
+    ///
+    /// The payload is copied in `dest`, returns the frame kind and the
+    /// payload length. An invalid header is reported once, the bytes are
+    /// dropped up to the next valid header. A partial frame is dropped when
+    /// its bytes stop coming.
+    pub fn read_frame(&mut self, dest: &mut [u8; MAX_FRAME_PAYLOAD], now_us: u64) -> Option<Result<(u8, usize), FrameError>> {
+        self.now_us = now_us;
+        self.fill_rx();
+        self.rx.next_frame(dest, now_us)
+    }
+
+    /// Start sending a frame
+    ///
+    /// Returns `WouldBlock` while the previous frame is being sent.
+    pub fn write_frame(&mut self, kind: u8, payload: &[u8]) -> Result<(), UsbError> {
+        if self.tx_busy() {
+            return Err(UsbError::WouldBlock);
+        }
+        if payload.len() > MAX_FRAME_PAYLOAD {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let len = payload.len() as u16;
+        self.tx[0..FRAME_HEADER_SIZE].copy_from_slice(&[kind, 0, len as u8, (len >> 8) as u8]);
+        self.tx[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        self.tx_len = FRAME_HEADER_SIZE + payload.len();
+        self.tx_pos = 0;
+        self.tx_zlp = self.tx_len % PACKET_SIZE == 0;
+        self.send_packet();
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// True while a frame is being sent
+    fn tx_busy(&self) -> bool {
+        self.tx_pos < self.tx_len || self.tx_zlp
+    }
+
+    /// Send the next packet of the frame, if the endpoint is free
+    fn send_packet(&mut self) {
+        if !self.tx_busy() {
+            return;
+        }
+        let end = core::cmp::min(self.tx_pos + PACKET_SIZE, self.tx_len);
+        if let Ok(count) = self.write_ep.write(&self.tx[self.tx_pos..end]) {
+            if count == 0 {
+                self.tx_zlp = false;
+            }
+            self.tx_pos += count;
+        }
+    }
+
+    /// Read the pending packet if it fits in the receive buffer
+    fn fill_rx(&mut self) {
+        let space = self.rx.free_space();
+        if space.len() < PACKET_SIZE {
+            return;
+        }
+        if let Ok(count) = self.read_ep.read(&mut space[0..PACKET_SIZE]) {
+            self.rx.commit(count, self.now_us);
+        }
+    }
+}
+
+// ============================================================================
+
+impl<'a> UsbClass<UsbBus> for VendorBulk<'a> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> usb_device::Result<()> {
+        writer.interface(self.interface, USB_CLASS_VENDOR, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+        Ok(())
+    }
+
+    fn get_bos_descriptors(&self, writer: &mut BosWriter) -> usb_device::Result<()> {
+        writer.capability(CAPABILITY_PLATFORM, &MS_OS_PLATFORM_CAPABILITY)
+    }
+
+    fn reset(&mut self) {
+        self.rx.clear();
+        self.tx_len = 0;
+        self.tx_pos = 0;
+        self.tx_zlp = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<UsbBus>) {
+        let req = xfer.request();
+        if req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Device
+            && req.request == MS_OS_VENDOR_CODE
+            && req.index == MS_OS_DESCRIPTOR_INDEX
+        {
+            xfer.accept_with(&self.ms_os_set).ok();
+        }
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr == self.read_ep.address() {
+            self.fill_rx();
+        }
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.send_packet();
+        }
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,146 @@
+// ============================================================================
+
+/// Simple buffer to handle incoming data
+///
+/// A line that does not fit in the buffer is dropped, up to its '\n', so
+/// the framing resumes on the next line.
+pub struct UsbBuffer<const CAPACITY: usize> {
+    // atomic bool mutex
+    buffer: [u8; CAPACITY],
+
+    /// current number of data loaded
+    size: usize,
+
+    /// True while dropping the end of a line that did not fit
+    discard: bool,
+}
+
+// ============================================================================
+
+impl<const CAPACITY: usize> UsbBuffer<CAPACITY> {
+    /// Create an empty buffer
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; CAPACITY],
+            size: 0,
+            discard: false,
+        }
+    }
+
+    /// Drop the buffered data
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.discard = false;
+    }
+
+    /// Load the buffer from usb serial
+    ///
+    /// When the data does not fit, the line being received is dropped: the
+    /// buffer keeps its complete commands and the input is skipped up to the
+    /// next '\n'.
+    pub fn load(&mut self, src: &[u8], count: usize) {
+        let mut data = &src[0..count.min(src.len())];
+
+        while !data.is_empty() {
+            // Skip the end of a dropped line
+            if self.discard {
+                match data.iter().position(|&c| c == b'\n') {
+                    None        => return,
+                    Some(index) => {
+                        self.discard = false;
+                        data = &data[index + 1..];
+                        continue;
+                    },
+                }
+            }
+
+            let count = data.len().min(CAPACITY - self.size);
+            self.buffer[self.size..self.size + count].copy_from_slice(&data[0..count]);
+            self.size += count;
+            data = &data[count..];
+
+            // Buffer full, drop the incomplete line at its end
+            if !data.is_empty() {
+                self.size = self.buffer[0..self.size].iter()
+                    .rposition(|&c| c == b'\n')
+                    .map_or(0, |index| index + 1);
+                self.discard = true;
+            }
+        }
+    }
+
+    /// Get the next complete line, without its '\n'
+    ///
+    /// The line is copied in `dest`, returns its length.
+    pub fn get_command(&mut self, dest: &mut [u8; CAPACITY]) -> Option<usize> {
+        // Init command buffer
+        let mut cmd: Option<usize> = None;
+
+        // Check for a complete command (end with \n or \r)
+        if let Some(index) = self.buffer[0..self.size].iter().position(|&c| c == b'\n') {
+            // Position is the index of the \r
+            let position: usize = index;
+
+            // count is the size of the command
+            let count = position + 1;
+
+            dest[0..position].copy_from_slice(&self.buffer[0..position]);
+            cmd = Some(position);
+            self.buffer.rotate_left(count);
+            self.size -= count;
+        }
+
+        cmd
+    }
+}
+
+impl<const CAPACITY: usize> Default for UsbBuffer<CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_of_the_buffer_size_is_accepted() {
+        let mut buffer = UsbBuffer::<8>::new();
+        let mut dest = [0u8; 8];
+        buffer.load(b"1234567\n", 8);
+        assert_eq!(buffer.get_command(&mut dest), Some(7));
+        assert_eq!(&dest[0..7], b"1234567");
+    }
+
+    #[test]
+    fn too_long_line_is_dropped_up_to_its_end() {
+        let mut buffer = UsbBuffer::<8>::new();
+        let mut dest = [0u8; 8];
+        buffer.load(b"12345", 5);
+        buffer.load(b"6789", 4);
+        buffer.load(b"abc\nok\n", 7);
+        assert_eq!(buffer.get_command(&mut dest), Some(2));
+        assert_eq!(&dest[0..2], b"ok");
+        assert_eq!(buffer.get_command(&mut dest), None);
+    }
+
+    #[test]
+    fn complete_commands_are_kept_on_overflow() {
+        let mut buffer = UsbBuffer::<8>::new();
+        let mut dest = [0u8; 8];
+        buffer.load(b"ab\ncd\nefgh\nij\n", 14);
+        assert_eq!(buffer.get_command(&mut dest), Some(2));
+        assert_eq!(&dest[0..2], b"ab");
+        assert_eq!(buffer.get_command(&mut dest), Some(2));
+        assert_eq!(&dest[0..2], b"cd");
+        assert_eq!(buffer.get_command(&mut dest), None);
+        buffer.load(b"ij\n", 3);
+        assert_eq!(buffer.get_command(&mut dest), Some(2));
+        assert_eq!(&dest[0..2], b"ij");
+    }
+}
+
+// ============================================================================
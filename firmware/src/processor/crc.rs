@@ -0,0 +1,47 @@
+// ============================================================================
+
+/// CRC-16/CCITT-FALSE polynomial
+const CRC16_POLY: u16 = 0x1021;
+
+/// CRC-16/CCITT-FALSE initial value
+const CRC16_INIT: u16 = 0xFFFF;
+
+// ============================================================================
+
+/// Simple bitwise CRC-16 (CCITT-FALSE) accumulator
+pub struct Crc16 {
+    /// current crc value
+    value: u16,
+}
+
+// ============================================================================
+
+impl Crc16 {
+    /// Create an accumulator with the initial value
+    pub fn new() -> Self {
+        Self {
+            value: CRC16_INIT,
+        }
+    }
+
+    /// Update the crc with the given bytes
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.value ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                if self.value & 0x8000 != 0 {
+                    self.value = (self.value << 1) ^ CRC16_POLY;
+                } else {
+                    self.value <<= 1;
+                }
+            }
+        }
+    }
+
+    /// Get the final crc value
+    pub fn finish(&self) -> u16 {
+        self.value
+    }
+}
+
+// ============================================================================
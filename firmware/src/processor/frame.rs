@@ -0,0 +1,184 @@
+// ============================================================================
+
+/// Frame header: kind, flags (0), then the payload length as u16 little endian
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+/// Max payload of a frame
+pub const MAX_FRAME_PAYLOAD: usize = 4096;
+
+/// Time without new bytes after which a partial frame is dropped (in us)
+pub const FRAME_TIMEOUT_US: u64 = 100_000;
+
+/// Errors of the frame reader
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    /// Bytes that do not start a valid frame were dropped
+    InvalidHeader,
+
+    /// The rest of a partial frame did not come in time, it was dropped
+    Timeout,
+}
+
+// ============================================================================
+
+/// Splits the received bytes into frames
+///
+/// A header with flags or a payload too large does not start a frame: the
+/// bytes are dropped up to the next valid header and the error is reported
+/// once. A header found in garbage may announce a payload that never comes,
+/// the partial frame is dropped after `FRAME_TIMEOUT_US` without new bytes.
+pub struct FrameReader<const SIZE: usize> {
+    /// Bytes received, a whole frame and the start of the next one
+    buffer: [u8; SIZE],
+
+    /// Number of bytes received
+    len: usize,
+
+    /// Time of the last bytes received (in us)
+    last_rx_us: u64,
+}
+
+// ============================================================================
+
+impl<const SIZE: usize> FrameReader<SIZE> {
+    /// Create an empty reader, `SIZE` must hold the biggest frame
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; SIZE],
+            len: 0,
+            last_rx_us: 0,
+        }
+    }
+
+    /// Drop the received bytes
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Free part of the buffer, to receive bytes in
+    pub fn free_space(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.len..]
+    }
+
+    /// Add the bytes received in the free part of the buffer, at `now_us`
+    pub fn commit(&mut self, count: usize, now_us: u64) {
+        self.len = (self.len + count).min(SIZE);
+        if count > 0 {
+            self.last_rx_us = now_us;
+        }
+    }
+
+    /// Get the next frame received
+    ///
+    /// The payload is copied in `dest`, returns the frame kind and the
+    /// payload length.
+    pub fn next_frame(&mut self, dest: &mut [u8; MAX_FRAME_PAYLOAD], now_us: u64) -> Option<Result<(u8, usize), FrameError>> {
+        // Resynchronise on the next valid header
+        let start = (0..self.len).find(|&p| self.may_start_frame(p)).unwrap_or(self.len);
+        if start > 0 {
+            self.drop_front(start);
+            return Some(Err(FrameError::InvalidHeader));
+        }
+        if self.len < FRAME_HEADER_SIZE {
+            return self.expire(now_us);
+        }
+
+        let len = u16::from_le_bytes([self.buffer[2], self.buffer[3]]) as usize;
+        let end = FRAME_HEADER_SIZE + len;
+        if self.len < end {
+            return self.expire(now_us);
+        }
+
+        let kind = self.buffer[0];
+        dest[0..len].copy_from_slice(&self.buffer[FRAME_HEADER_SIZE..end]);
+        self.drop_front(end);
+        Some(Ok((kind, len)))
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Drop the partial frame when its bytes stopped coming
+    fn expire(&mut self, now_us: u64) -> Option<Result<(u8, usize), FrameError>> {
+        if self.len > 0 && now_us.saturating_sub(self.last_rx_us) >= FRAME_TIMEOUT_US {
+            self.clear();
+            return Some(Err(FrameError::Timeout));
+        }
+        None
+    }
+
+    /// True if the bytes received from `pos` can start a frame
+    fn may_start_frame(&self, pos: usize) -> bool {
+        let header = &self.buffer[pos..self.len.min(pos + FRAME_HEADER_SIZE)];
+        if header.len() > 1 && header[1] != 0 {
+            return false;
+        }
+        match header.len() == FRAME_HEADER_SIZE {
+            true  => u16::from_le_bytes([header[2], header[3]]) as usize <= MAX_FRAME_PAYLOAD,
+            false => true,
+        }
+    }
+
+    /// Remove the first bytes of the buffer
+    fn drop_front(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.len, 0);
+        self.len -= count;
+    }
+}
+
+impl<const SIZE: usize> Default for FrameReader<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed<const SIZE: usize>(reader: &mut FrameReader<SIZE>, bytes: &[u8], now_us: u64) {
+        reader.free_space()[0..bytes.len()].copy_from_slice(bytes);
+        reader.commit(bytes.len(), now_us);
+    }
+
+    #[test]
+    fn frames_are_split() {
+        let mut reader = FrameReader::<64>::new();
+        let mut dest = [0u8; MAX_FRAME_PAYLOAD];
+        feed(&mut reader, &[1, 0, 2, 0, b'a', b'b', 3, 0, 1], 0);
+        assert_eq!(reader.next_frame(&mut dest, 0), Some(Ok((1, 2))));
+        assert_eq!(&dest[0..2], b"ab");
+        assert_eq!(reader.next_frame(&mut dest, 0), None);
+        feed(&mut reader, &[0, b'c'], 10);
+        assert_eq!(reader.next_frame(&mut dest, 10), Some(Ok((3, 1))));
+        assert_eq!(dest[0], b'c');
+    }
+
+    #[test]
+    fn invalid_header_resyncs_on_the_next_frame() {
+        let mut reader = FrameReader::<64>::new();
+        let mut dest = [0u8; MAX_FRAME_PAYLOAD];
+        // Payload too large, then garbage with flags, then a valid frame
+        feed(&mut reader, &[1, 0, 0xFF, 0xFF, 7, 7, 1, 0, 1, 0, b'x'], 0);
+        assert_eq!(reader.next_frame(&mut dest, 0), Some(Err(FrameError::InvalidHeader)));
+        assert_eq!(reader.next_frame(&mut dest, 0), Some(Ok((1, 1))));
+        assert_eq!(dest[0], b'x');
+        assert_eq!(reader.next_frame(&mut dest, 0), None);
+    }
+
+    #[test]
+    fn partial_frame_is_dropped_after_the_timeout() {
+        let mut reader = FrameReader::<64>::new();
+        let mut dest = [0u8; MAX_FRAME_PAYLOAD];
+        // Garbage that looks like the header of a long frame
+        feed(&mut reader, &[5, 0, 0x00, 0x10, 1, 2], 1_000);
+        assert_eq!(reader.next_frame(&mut dest, 1_000 + FRAME_TIMEOUT_US - 1), None);
+        assert_eq!(reader.next_frame(&mut dest, 1_000 + FRAME_TIMEOUT_US), Some(Err(FrameError::Timeout)));
+        feed(&mut reader, &[1, 0, 1, 0, b'y'], 200_000);
+        assert_eq!(reader.next_frame(&mut dest, 200_000), Some(Ok((1, 1))));
+        assert_eq!(dest[0], b'y');
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,765 @@
+// ============================================================================
+
+// Core
+use core::fmt::Write;
+use core::str::FromStr;
+
+// Algos
+use heapless::Vec;
+
+// ============================================================================
+
+pub mod protocol;
+use protocol::{Answer, AnswerStatus, AnswerText, Command, CommandCode};
+use protocol::{Batch, BatchAnswer, DataAnswer, InfoAnswer, Response};
+use protocol::{CmdCrcModeValue, CmdPinDirValue, CmdPinWriteValue, CmdProtocolValue};
+
+// Integrity check
+mod crc;
+
+// Line buffering
+pub mod buffer;
+use buffer::UsbBuffer;
+
+// Framing of the vendor interface
+pub mod frame;
+
+// SCPI front end
+pub mod scpi;
+use scpi::{ScpiError, ScpiParser, ScpiQuery, ScpiReply, ScpiRequest};
+
+// Pin backends
+pub mod pins;
+use pins::{PinBackend, PinMode};
+
+pub mod sim_pins;
+
+// ============================================================================
+
+/// Size of the incoming command buffer, must hold a full batch line
+pub const CMD_BUFFER_SIZE: usize = 2048;
+
+/// Default timeout of the wait command (in us)
+const DEFAULT_WAIT_TIMEOUT_US: u32 = 1_000_000;
+
+/// Longest delay and wait timeout (in us), the usb is not polled meanwhile
+const MAX_WAIT_US: u32 = 1_000_000;
+
+// ============================================================================
+
+/// State of the command processing
+pub struct ProcessorState {
+    /// Buffer to hold incomnig data
+    usb_buffer: UsbBuffer<CMD_BUFFER_SIZE>,
+
+    /// When true, commands without crc field are rejected
+    crc_strict: bool,
+
+    /// When true, the serial lines are SCPI commands instead of json
+    scpi_mode: bool,
+
+    /// SCPI front end state
+    scpi: ScpiParser,
+}
+
+impl ProcessorState {
+    /// Create the state, json protocol without strict crc
+    pub fn new() -> Self {
+        Self {
+            usb_buffer: UsbBuffer::new(),
+            crc_strict: false,
+            scpi_mode:  false,
+            scpi:       ScpiParser::new(),
+        }
+    }
+
+    /// Drop the partial commands and select the json protocol again
+    pub fn reset(&mut self) {
+        self.usb_buffer.clear();
+        self.scpi_mode = false;
+        self.scpi.clear();
+    }
+}
+
+impl Default for ProcessorState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+
+/// Command processing, shared by the device and the host builds
+///
+/// The implementor provides the pins, the time and the commands tied to its
+/// hardware. The protocol handling (json lines, batches, SCPI, integrity)
+/// and the plain io commands are provided.
+pub trait CommandProcessor {
+    /// Pins of the plain io commands
+    type Pins: PinBackend;
+
+    /// State of the command processing
+    fn state(&self) -> &ProcessorState;
+    fn state_mut(&mut self) -> &mut ProcessorState;
+
+    /// Pins of the plain io commands
+    fn pins(&mut self) -> &mut Self::Pins;
+
+    /// Time since boot (in us)
+    fn now_us(&self) -> u64;
+
+    /// Busy-wait the given number of microseconds
+    fn delay_us(&mut self, us: u32);
+
+    /// Put the device in its safe state
+    fn apply_safe_state(&mut self);
+
+    /// To identify the device, answers with its label and serial number
+    fn process_test(&self) -> InfoAnswer;
+
+    /// Reply of the SCPI *IDN? query
+    fn write_identification(&self, reply: &mut ScpiReply);
+
+    /// Execute a validated command tied to the hardware of the device
+    fn execute_device_command(&mut self, code: CommandCode, cmd: &Command) -> Answer;
+
+    /// Execute a validated command answering with data
+    fn process_data_command(&mut self, code: CommandCode, cmd: &Command) -> DataAnswer;
+
+    // ------------------------------------------------------------------------
+
+    /// To configure the  mode of the io
+    ///
+    fn process_set_io_mode(&mut self, cmd: &Command) -> Answer {
+        if !self.pins().is_valid(cmd.pin) {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        let mode = match CmdPinDirValue::from_u8(cmd.arg) {
+            Some(CmdPinDirValue::PullUpInput)    => PinMode::PullUpInput,
+            Some(CmdPinDirValue::PullDownInput)  => PinMode::PullDownInput,
+            Some(CmdPinDirValue::ReadableOutput) => PinMode::Output,
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Invalid arg: {}", cmd.arg).unwrap();
+
+                return Answer::error(0, 0, txt);
+            },
+        };
+
+        match self.pins().set_mode(cmd.pin, mode) {
+            Ok(())  => Answer::ok(cmd.pin, 0, AnswerText::from_str("m").unwrap()),
+            Err(_)  => Answer::error(0, 0, AnswerText::from_str("Cannot set desired I/O mode").unwrap()),
+        }
+    }
+
+    /// To write a value on the io
+    fn process_write_io(&mut self, cmd: &Command) -> Answer {
+        if !self.pins().is_valid(cmd.pin) {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        let level = match CmdPinWriteValue::from_u8(cmd.arg) {
+            Some(CmdPinWriteValue::High) => true,
+            Some(CmdPinWriteValue::Low)  => false,
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Invalid arg: {}", cmd.arg).unwrap();
+
+                return Answer::error(cmd.pin, 0, txt);
+            },
+        };
+
+        match self.pins().write(cmd.pin, level) {
+            Ok(())  => Answer::ok(cmd.pin, 0, AnswerText::from_str("m").unwrap()),
+            Err(_)  => Answer::error(
+                cmd.pin,
+                0,
+                AnswerText::from_str("Cannot set desired pin value. Is direction correct?").unwrap(),
+            ),
+        }
+    }
+
+    /// To read an io
+    fn process_read_io(&mut self, cmd: &Command) -> Answer {
+        if !self.pins().is_valid(cmd.pin) {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        match self.pins().read(cmd.pin) {
+            Ok(v) => Answer::ok(
+                cmd.pin,
+                match v { true => 1, false => 0},
+                AnswerText::from_str("r").unwrap(),
+            ),
+
+            Err(_) => Answer::error(
+                cmd.pin,
+                0,
+                AnswerText::from_str("Cannot read pin value. Is direction correct?").unwrap()
+            )
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To busy-wait the given number of microseconds
+    fn process_delay(&mut self, cmd: &Command) -> Answer {
+        match cmd.val {
+            Some(us) if us > MAX_WAIT_US => Answer::error(0, 0, AnswerText::from_str("Delay too long").unwrap()),
+            Some(us) => {
+                self.delay_us(us);
+                Answer::ok(0, 0, AnswerText::from_str("d").unwrap()).with_val(us)
+            },
+
+            None => Answer::error(0, 0, AnswerText::from_str("Missing val").unwrap()),
+        }
+    }
+
+    /// To wait until the io reaches the given level
+    ///
+    /// val is the timeout in us, the answer returns the elapsed time in us
+    fn process_wait_io(&mut self, cmd: &Command) -> Answer {
+        let expected = match CmdPinWriteValue::from_u8(cmd.arg) {
+            Some(CmdPinWriteValue::High) => true,
+            Some(CmdPinWriteValue::Low)  => false,
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Invalid arg: {}", cmd.arg).unwrap();
+
+                return Answer::error(cmd.pin, 0, txt);
+            }
+        };
+        let timeout = cmd.val.unwrap_or(DEFAULT_WAIT_TIMEOUT_US);
+        if timeout > MAX_WAIT_US {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Timeout too long").unwrap());
+        }
+
+        if !self.pins().is_valid(cmd.pin) {
+            return Answer::error(cmd.pin, 0, AnswerText::from_str("Invalid pin").unwrap());
+        }
+
+        let start = self.now_us();
+        loop {
+            // Saturated, a truncated elapsed time could wrap under the timeout
+            let elapsed = (self.now_us() - start).min(u32::MAX as u64) as u32;
+
+            match self.pins().read(cmd.pin) {
+                Ok(v) if v == expected => {
+                    return Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("w").unwrap()).with_val(elapsed);
+                },
+
+                Ok(_) => {},
+
+                Err(_) => {
+                    return Answer::error(
+                        cmd.pin,
+                        0,
+                        AnswerText::from_str("Cannot read pin value. Is direction correct?").unwrap()
+                    );
+                }
+            }
+
+            if elapsed >= timeout {
+                return Answer::error(cmd.pin, 0, AnswerText::from_str("Timeout").unwrap()).with_val(elapsed);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// To switch between optional and mandatory crc
+    fn process_set_crc_mode(&mut self, cmd: &Command) -> Answer {
+        match CmdCrcModeValue::from_u8(cmd.arg) {
+            Some(x) => {
+                self.state_mut().crc_strict = match x {
+                    CmdCrcModeValue::Optional => false,
+                    CmdCrcModeValue::Strict   => true,
+                };
+
+                Answer::ok(0, cmd.arg, AnswerText::from_str("c").unwrap())
+            },
+
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Invalid arg: {}", cmd.arg).unwrap();
+
+                Answer::error(0, 0, txt)
+            }
+        }
+    }
+
+    /// To select the protocol of the serial lines
+    ///
+    /// arg 0 json, arg 1 SCPI. The answer is still sent in json, the SCPI
+    /// command SYSTem:PROTocol JSON switches back.
+    fn process_protocol_select(&mut self, cmd: &Command) -> Answer {
+        let scpi_mode = match CmdProtocolValue::from_u8(cmd.arg) {
+            Some(CmdProtocolValue::Json) => false,
+            Some(CmdProtocolValue::Scpi) => true,
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Invalid arg: {}", cmd.arg).unwrap();
+
+                return Answer::error(cmd.pin, 0, txt);
+            },
+        };
+        let state = self.state_mut();
+        state.scpi_mode = scpi_mode;
+        state.scpi.clear();
+        Answer::ok(cmd.pin, cmd.arg, AnswerText::from_str("Protocol selected").unwrap())
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Check the integrity field of the command
+    ///
+    /// Returns an error answer if the command must not be executed
+    fn check_command_crc(&self, cmd: &Command) -> Option<Answer> {
+        match cmd.crc {
+            Some(crc) => {
+                let expected = cmd.payload_crc();
+                if crc != expected {
+                    let mut txt = AnswerText::new();
+                    write!(txt, "Invalid crc: {} (expected {})", crc, expected).unwrap();
+
+                    Some(Answer::error(cmd.pin, 0, txt))
+                }
+                else {
+                    None
+                }
+            },
+
+            None => match self.state().crc_strict {
+                true  => Some(Answer::error(cmd.pin, 0, AnswerText::from_str("Missing crc").unwrap())),
+                false => None,
+            }
+        }
+    }
+
+    /// Check that the command can be executed (integrity and code)
+    ///
+    /// Returns an error answer if the command must not be executed
+    fn validate_command(&self, cmd: &Command) -> Option<Answer> {
+        // Reject corrupted commands before execution
+        if let Some(err) = self.check_command_crc(cmd) {
+            return Some(err);
+        }
+
+        match CommandCode::from_u8(cmd.cod) {
+            Some(_) => None,
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Uknown command code: {}", cmd.cod).unwrap();
+
+                Some(Answer::error(0, 0, txt))
+            },
+        }
+    }
+
+    /// Execute a single validated command
+    fn execute_command(&mut self, cmd: &Command) -> Answer {
+        match CommandCode::from_u8(cmd.cod) {
+            Some(code) => match code {
+                CommandCode::SetDirection   => self.process_set_io_mode(cmd),
+                CommandCode::WriteValue     => self.process_write_io(cmd),
+                CommandCode::ReadValue      => self.process_read_io(cmd),
+                CommandCode::Delay          => self.process_delay(cmd),
+                CommandCode::WaitValue      => self.process_wait_io(cmd),
+                CommandCode::Test           => Answer::ok(0, 1, AnswerText::from_str("").unwrap()),
+                CommandCode::SetCrcMode     => self.process_set_crc_mode(cmd),
+                CommandCode::ProtocolSelect => self.process_protocol_select(cmd),
+                _ if code.is_data()         => Answer::error(0, 0, AnswerText::from_str("Data command not allowed in batch").unwrap()),
+                _                           => self.execute_device_command(code, cmd),
+            },
+
+            None => {
+                let mut txt = AnswerText::new();
+                write!(txt, "Uknown command code: {}", cmd.cod).unwrap();
+
+                Answer::error(0, 0, txt)
+            },
+        }
+    }
+
+    /// Process a single command
+    fn process_command(&mut self, cmd: &Command) -> Response {
+        if let Some(err) = self.validate_command(cmd) {
+            return Response::Single(err);
+        }
+
+        match CommandCode::from_u8(cmd.cod) {
+            Some(CommandCode::Test)      => Response::Info(self.process_test()),
+            Some(code) if code.is_data() => Response::Data(self.process_data_command(code, cmd)),
+            _ => Response::Single(self.execute_command(cmd)),
+        }
+    }
+
+    /// Process a batch of commands
+    ///
+    /// Every operation is validated before the first one is executed, then
+    /// they run back-to-back. The execution stops on the first error.
+    fn process_batch(&mut self, batch: &Batch) -> BatchAnswer {
+        let mut answer = BatchAnswer {
+            sts: AnswerStatus::Ok,
+            res: Vec::new(),
+        };
+
+        // Validate the whole batch first
+        for op in batch.bat.iter() {
+            let cmd = &Command::from(op);
+            if CommandCode::from_u8(cmd.cod).is_some_and(|code| code.is_data()) {
+                answer.sts = AnswerStatus::Error;
+                answer.res.push(Answer::error(0, 0, AnswerText::from_str("Data command not allowed in batch").unwrap())).unwrap();
+                return answer;
+            }
+            if let Some(err) = self.validate_command(cmd) {
+                answer.sts = AnswerStatus::Error;
+                answer.res.push(err).unwrap();
+                return answer;
+            }
+        }
+
+        // Then execute it without interruption
+        for op in batch.bat.iter() {
+            let res = self.execute_command(&Command::from(op));
+            let is_ok = res.is_ok();
+
+            answer.res.push(res).unwrap();
+            if !is_ok {
+                answer.sts = AnswerStatus::Error;
+                break;
+            }
+        }
+
+        answer
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Process a json line, a single command or a batch
+    fn process_line(&mut self, line: &[u8]) -> Response {
+        match serde_json_core::de::from_slice::<Command>(line) {
+            // Process received command
+            Ok(cmd) => self.process_command(&cmd.0),
+
+            // Not a single command, try a batch
+            Err(_e) => match serde_json_core::de::from_slice::<Batch>(line) {
+                Ok(batch) => Response::Batch(self.process_batch(&batch.0)),
+
+                // Process parsing error
+                Err(_) => {
+                    let mut txt = AnswerText::new();
+                    write!(txt, "Error: {}", _e).unwrap();
+
+                    Response::Single(Answer::error(0, 0, txt))
+                },
+            },
+        }
+    }
+
+    /// Process a SCPI line, the program message units are separated by ';'
+    ///
+    /// Only the queries are answered, with their replies joined by ';'. The
+    /// first error is queued for SYSTem:ERRor? and ends the line.
+    fn process_scpi_line(&mut self, line: &[u8]) -> Option<Response> {
+        let text = match core::str::from_utf8(line) {
+            Ok(text) => text,
+            Err(_) => {
+                self.state_mut().scpi.push_error(ScpiError::new(scpi::ERR_SYNTAX, "Invalid character"));
+                return None;
+            },
+        };
+
+        let mut reply = ScpiReply::new();
+        for unit in text.split(';').filter(|u| !u.trim().is_empty()) {
+            let mut unit_reply = ScpiReply::new();
+            let result = match ScpiParser::parse(unit) {
+                Ok(request) => self.execute_scpi_request(request, &mut unit_reply),
+                Err(err)    => Err(err),
+            };
+            if let Err(err) = result {
+                self.state_mut().scpi.push_error(err);
+                break;
+            }
+
+            if !unit_reply.is_empty() {
+                if !reply.is_empty() {
+                    reply.push(';').ok();
+                }
+                reply.push_str(unit_reply.as_str()).ok();
+            }
+        }
+
+        match reply.is_empty() {
+            true  => None,
+            false => Some(Response::Scpi(reply)),
+        }
+    }
+
+    /// Execute a SCPI request, the reply of a query is written in `reply`
+    fn execute_scpi_request(&mut self, request: ScpiRequest, reply: &mut ScpiReply) -> Result<(), ScpiError> {
+        match request {
+            ScpiRequest::Identify          => self.write_identification(reply),
+            ScpiRequest::Reset             => self.apply_safe_state(),
+            ScpiRequest::ClearStatus       => self.state_mut().scpi.clear(),
+            ScpiRequest::OperationComplete => { reply.push('1').ok(); },
+            ScpiRequest::ErrorQuery        => self.state_mut().scpi.pop_error(reply),
+            ScpiRequest::SelectJson        => self.state_mut().scpi_mode = false,
+            ScpiRequest::Command { code, pin, arg, query } => {
+                let cmd = scpi::command(code, pin, arg);
+                // Same checks as the json commands, the strict crc mode
+                // rejects them as they cannot carry a crc
+                let answer = match self.validate_command(&cmd) {
+                    Some(err) => err,
+                    None      => self.execute_command(&cmd),
+                };
+                if !answer.is_ok() {
+                    return Err(ScpiError::new(scpi::ERR_EXECUTION, answer.msg.as_str()));
+                }
+                match query {
+                    ScpiQuery::None  => {},
+                    ScpiQuery::Level => { write!(reply, "{}", answer.arg).ok(); },
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Process incoming commands
+    ///
+    fn update_command_processing(&mut self) -> Option<Response> {
+        let mut cmd_buffer = [0u8; CMD_BUFFER_SIZE];
+
+        match self.state_mut().usb_buffer.get_command(&mut cmd_buffer) {
+            None => None,
+            Some(cmd_end_index) => match self.state().scpi_mode {
+                true  => self.process_scpi_line(&cmd_buffer[0..cmd_end_index]),
+                false => Some(self.process_line(&cmd_buffer[0..cmd_end_index])),
+            },
+        }
+    }
+
+    /// Feed input buffer
+    ///
+    fn feed_cmd_buffer(&mut self, buf: &[u8], count: usize) {
+        self.state_mut().usb_buffer.load(buf, count);
+    }
+}
+
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::sim_pins::SimPins;
+
+    /// Device without hardware commands, on simulated pins
+    struct TestDevice {
+        state: ProcessorState,
+        pins: SimPins,
+        now: core::cell::Cell<u64>,
+    }
+
+    impl TestDevice {
+        fn new() -> Self {
+            Self {
+                state: ProcessorState::new(),
+                pins: SimPins::new(),
+                now: core::cell::Cell::new(0),
+            }
+        }
+
+        /// Feed a line and serialize the response
+        fn exchange(&mut self, line: &str) -> Option<std::string::String> {
+            self.feed_cmd_buffer(line.as_bytes(), line.len());
+            self.update_command_processing().map(|response| match response {
+                Response::Scpi(reply) => std::string::String::from(reply.as_str()),
+                response => {
+                    let mut buffer = [0u8; 4096];
+                    let size = response.serialize(&mut buffer);
+                    std::string::String::from_utf8(buffer[0..size].to_vec()).unwrap()
+                },
+            })
+        }
+    }
+
+    impl CommandProcessor for TestDevice {
+        type Pins = SimPins;
+
+        fn state(&self) -> &ProcessorState {
+            &self.state
+        }
+
+        fn state_mut(&mut self) -> &mut ProcessorState {
+            &mut self.state
+        }
+
+        fn pins(&mut self) -> &mut SimPins {
+            &mut self.pins
+        }
+
+        fn now_us(&self) -> u64 {
+            // Time only moves when read
+            self.now.set(self.now.get() + 10);
+            self.now.get()
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            self.now.set(self.now.get() + us as u64);
+        }
+
+        fn apply_safe_state(&mut self) {
+            for pin in 0..30 {
+                self.pins.set_mode(pin, PinMode::PullDownInput).ok();
+            }
+        }
+
+        fn process_test(&self) -> InfoAnswer {
+            InfoAnswer {
+                sts: AnswerStatus::Ok,
+                pin: 0,
+                arg: 1,
+                msg: AnswerText::new(),
+                lbl: heapless::String::new(),
+                ser: heapless::String::from("TEST"),
+            }
+        }
+
+        fn write_identification(&self, reply: &mut ScpiReply) {
+            reply.push_str("test,picoha-io,TEST,0").ok();
+        }
+
+        fn execute_device_command(&mut self, _code: CommandCode, _cmd: &Command) -> Answer {
+            Answer::error(0, 0, AnswerText::from_str("Not available").unwrap())
+        }
+
+        fn process_data_command(&mut self, _code: CommandCode, _cmd: &Command) -> DataAnswer {
+            DataAnswer::from(Answer::error(0, 0, AnswerText::from_str("Not available").unwrap()))
+        }
+    }
+
+    #[test]
+    fn write_then_read_through_a_wire() {
+        let mut dev = TestDevice::new();
+        dev.pins.connect(1, 2).unwrap();
+
+        assert_eq!(dev.exchange("{\"cod\":0,\"pin\":1,\"arg\":2}\n").unwrap(), "{\"sts\":0,\"pin\":1,\"arg\":0,\"msg\":\"m\"}");
+        assert_eq!(dev.exchange("{\"cod\":1,\"pin\":1,\"arg\":1}\n").unwrap(), "{\"sts\":0,\"pin\":1,\"arg\":0,\"msg\":\"m\"}");
+        assert_eq!(dev.exchange("{\"cod\":2,\"pin\":2,\"arg\":0}\n").unwrap(), "{\"sts\":0,\"pin\":2,\"arg\":1,\"msg\":\"r\"}");
+    }
+
+    #[test]
+    fn write_on_an_input_fails() {
+        let mut dev = TestDevice::new();
+        let answer = dev.exchange("{\"cod\":1,\"pin\":3,\"arg\":1}\n").unwrap();
+        assert!(answer.contains("Is direction correct?"));
+        let answer = dev.exchange("{\"cod\":1,\"pin\":24,\"arg\":1}\n").unwrap();
+        assert!(answer.contains("Invalid pin"));
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let mut dev = TestDevice::new();
+        let answer = dev.exchange("{\"cod\":4,\"pin\":5,\"arg\":1,\"val\":100}\n").unwrap();
+        assert!(answer.starts_with("{\"sts\":1,\"pin\":5,\"arg\":0,\"msg\":\"Timeout\""));
+    }
+
+    #[test]
+    fn long_delays_are_rejected() {
+        let mut dev = TestDevice::new();
+        assert!(dev.exchange("{\"cod\":3,\"pin\":0,\"arg\":0,\"val\":1000001}\n").unwrap().contains("Delay too long"));
+        assert!(dev.exchange("{\"cod\":4,\"pin\":5,\"arg\":1,\"val\":1000001}\n").unwrap().contains("Timeout too long"));
+    }
+
+    #[test]
+    fn strict_crc_rejects_commands_without_crc() {
+        let mut dev = TestDevice::new();
+        dev.exchange("{\"cod\":11,\"pin\":0,\"arg\":1}\n").unwrap();
+        let answer = dev.exchange("{\"cod\":2,\"pin\":5,\"arg\":0}\n").unwrap();
+        assert!(answer.contains("Missing crc"));
+    }
+
+    #[test]
+    fn batch_stops_on_the_first_error() {
+        let mut dev = TestDevice::new();
+        let answer = dev.exchange("{\"bat\":[{\"cod\":0,\"pin\":6,\"arg\":2},{\"cod\":1,\"pin\":7,\"arg\":1},{\"cod\":1,\"pin\":6,\"arg\":1}]}\n").unwrap();
+        assert!(answer.starts_with("{\"sts\":1,\"res\":[{\"sts\":0"));
+        assert_eq!(answer.matches("\"sts\"").count(), 3);
+        assert_eq!(dev.pins.level(6), Some(false));
+    }
+
+    #[test]
+    fn too_long_batch_answer_is_replaced_by_an_error() {
+        let msg = AnswerText::from_str(core::str::from_utf8(&[b'x'; protocol::MAX_MSG_SIZE]).unwrap()).unwrap();
+        let mut batch = BatchAnswer {
+            sts: AnswerStatus::Ok,
+            res: Vec::new(),
+        };
+        for _ in 0..protocol::MAX_BATCH_SIZE {
+            let answer = Answer::ok(0, 0, msg.clone()).with_val(u32::MAX).with_prm(&[i32::MIN; protocol::MAX_PRM_SIZE]);
+            batch.res.push(answer).unwrap();
+        }
+
+        let mut buffer = [0u8; 4096];
+        let size = Response::Batch(batch).serialize(&mut buffer);
+        assert_eq!(&buffer[0..size], b"{\"sts\":1,\"pin\":0,\"arg\":0,\"msg\":\"Answer too long\"}");
+    }
+
+    #[test]
+    fn data_commands_are_rejected_in_batch() {
+        let mut dev = TestDevice::new();
+        let answer = dev.exchange("{\"bat\":[{\"cod\":18,\"pin\":0,\"arg\":0}]}\n").unwrap();
+        assert!(answer.contains("Data command not allowed in batch"));
+    }
+
+    #[test]
+    fn scpi_commands_are_rejected_in_strict_crc_mode() {
+        let mut dev = TestDevice::new();
+        dev.state.crc_strict = true;
+        dev.state.scpi_mode = true;
+        assert_eq!(dev.exchange("DIG:PIN5:DIR OUT;:DIG:PIN5 1\n"), None);
+        assert_eq!(dev.pins.mode(5), Some(PinMode::PullDownInput));
+        assert_eq!(dev.exchange("SYST:ERR?\n").unwrap(), "-200,\"Missing crc\"");
+        assert!(dev.exchange("*IDN?\n").is_some());
+    }
+
+    #[test]
+    fn batch_operations_take_no_data() {
+        let mut dev = TestDevice::new();
+        let answer = dev.exchange("{\"bat\":[{\"cod\":0,\"pin\":6,\"arg\":2,\"dat\":\"AA==\"}]}\n").unwrap();
+        assert!(answer.starts_with("{\"sts\":1"));
+        assert_eq!(dev.pins.mode(6), Some(PinMode::PullDownInput));
+    }
+
+    #[test]
+    fn scpi_lines_map_on_the_same_commands() {
+        let mut dev = TestDevice::new();
+        dev.pins.connect(8, 9).unwrap();
+        dev.exchange("{\"cod\":51,\"pin\":0,\"arg\":1}\n").unwrap();
+
+        assert_eq!(dev.exchange("DIG:PIN8:DIR OUT;:dig:pin8 ON\n"), None);
+        assert_eq!(dev.exchange("DIGital:PIN9?;*OPC?\n").unwrap(), "1;1");
+        assert_eq!(dev.exchange("*IDN?\n").unwrap(), "test,picoha-io,TEST,0");
+
+        assert_eq!(dev.exchange("DIG:PIN30?\n"), None);
+        assert_eq!(dev.exchange("SYST:ERR?\n").unwrap(), "-200,\"Invalid pin\"");
+        assert_eq!(dev.exchange("SYST:ERR?\n").unwrap(), "0,\"No error\"");
+
+        assert_eq!(dev.exchange("SYST:PROT JSON\n"), None);
+        assert!(dev.exchange("{\"cod\":2,\"pin\":9,\"arg\":0}\n").unwrap().starts_with("{\"sts\":0"));
+    }
+
+    #[test]
+    fn reset_selects_json_again() {
+        let mut dev = TestDevice::new();
+        dev.exchange("{\"cod\":51,\"pin\":0,\"arg\":1}\n").unwrap();
+        dev.feed_cmd_buffer(b"*IDN", 4);
+        dev.state_mut().reset();
+        assert!(dev.exchange("{\"cod\":2,\"pin\":9,\"arg\":0}\n").unwrap().starts_with("{\"sts\":0"));
+    }
+}
+
+// ============================================================================
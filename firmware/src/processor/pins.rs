@@ -0,0 +1,46 @@
+// ============================================================================
+
+/// Modes of a gpio used as a plain io
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PinMode {
+    /// Input with the pull-up enabled
+    PullUpInput,
+
+    /// Input with the pull-down enabled
+    PullDownInput,
+
+    /// Output, its level can be read back
+    Output,
+}
+
+/// Errors of the pin backends
+#[derive(Debug)]
+pub enum PinError {
+    /// The pin does not exist or is not available
+    InvalidPin,
+
+    /// The operation is not allowed in the current mode of the pin
+    InvalidMode,
+}
+
+// ============================================================================
+
+/// Access to the gpios used by the plain io commands
+///
+/// The RP2040 pins implement it on the device, a simulated backend on the
+/// host.
+pub trait PinBackend {
+    /// True if the pin can be used
+    fn is_valid(&self, idx: u8) -> bool;
+
+    /// Configure the pin as a plain io
+    fn set_mode(&mut self, idx: u8, mode: PinMode) -> Result<(), PinError>;
+
+    /// Drive the level of an output pin
+    fn write(&mut self, idx: u8, level: bool) -> Result<(), PinError>;
+
+    /// Read the level of the pin
+    fn read(&mut self, idx: u8) -> Result<bool, PinError>;
+}
+
+// ============================================================================
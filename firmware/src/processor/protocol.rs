@@ -0,0 +1,743 @@
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_repr::{Serialize_repr};
+use heapless::{String, Vec};
+
+use super::crc::Crc16;
+use super::scpi::ScpiReply;
+
+// ============================================================================
+
+/// Max message string length in answer
+pub const MAX_MSG_SIZE: usize = 128;
+
+/// Max number of extended parameters in a command
+pub const MAX_PRM_SIZE: usize = 8;
+
+/// Max data string length (base64) in commands
+pub const MAX_CMD_DAT_SIZE: usize = 1024;
+
+/// Max data string length (base64) in data answers
+pub const MAX_DAT_SIZE: usize = 1024;
+
+/// Max number of operations in a batch
+pub const MAX_BATCH_SIZE: usize = 16;
+
+/// Max label length in the discovery answer
+pub const MAX_LABEL_SIZE: usize = 32;
+
+/// Max serial number length in the discovery answer
+pub const MAX_SERIAL_SIZE: usize = 64;
+
+// ============================================================================
+
+/// Represents the command codes as an enum
+//#[derive(Deserialize_repr, Debug)]
+pub enum CommandCode {
+    SetDirection,
+    WriteValue,
+    ReadValue,
+    Delay,
+    WaitValue,
+    Pulse,
+    Measure,
+    CounterStart,
+    CounterRead,
+    CounterStop,
+    Test,
+    SetCrcMode,
+    QuadStart,
+    QuadRead,
+    QuadPreset,
+    QuadStop,
+    CaptureStart,
+    CaptureStatus,
+    CaptureRead,
+    CaptureStop,
+    PatternLoad,
+    PatternStart,
+    PatternStop,
+    PatternStatus,
+    LedWrite,
+    ServoStart,
+    ServoSet,
+    ServoRead,
+    ServoStop,
+    StepperSetup,
+    StepperMove,
+    StepperHome,
+    StepperStatus,
+    StepperStop,
+    StepperRelease,
+    OneWireReset,
+    OneWireTransfer,
+    OneWireSearch,
+    OneWireTemperature,
+    I2cTargetStart,
+    I2cTargetLoad,
+    I2cTargetDump,
+    I2cTargetEvents,
+    I2cTargetStop,
+    SpiTargetStart,
+    SpiTargetLoad,
+    SpiTargetLog,
+    SpiTargetStop,
+    Reboot,
+    IdentitySet,
+    SafeStateMode,
+    ProtocolSelect,
+}
+
+impl CommandCode {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0  => Some(Self::SetDirection),
+            1  => Some(Self::WriteValue),
+            2  => Some(Self::ReadValue),
+            3  => Some(Self::Delay),
+            4  => Some(Self::WaitValue),
+            5  => Some(Self::Pulse),
+            6  => Some(Self::Measure),
+            7  => Some(Self::CounterStart),
+            8  => Some(Self::CounterRead),
+            9  => Some(Self::CounterStop),
+            10 => Some(Self::Test),
+            11 => Some(Self::SetCrcMode),
+            12 => Some(Self::QuadStart),
+            13 => Some(Self::QuadRead),
+            14 => Some(Self::QuadPreset),
+            15 => Some(Self::QuadStop),
+            16 => Some(Self::CaptureStart),
+            17 => Some(Self::CaptureStatus),
+            18 => Some(Self::CaptureRead),
+            19 => Some(Self::CaptureStop),
+            20 => Some(Self::PatternLoad),
+            21 => Some(Self::PatternStart),
+            22 => Some(Self::PatternStop),
+            23 => Some(Self::PatternStatus),
+            24 => Some(Self::LedWrite),
+            25 => Some(Self::ServoStart),
+            26 => Some(Self::ServoSet),
+            27 => Some(Self::ServoRead),
+            28 => Some(Self::ServoStop),
+            29 => Some(Self::StepperSetup),
+            30 => Some(Self::StepperMove),
+            31 => Some(Self::StepperHome),
+            32 => Some(Self::StepperStatus),
+            33 => Some(Self::StepperStop),
+            34 => Some(Self::StepperRelease),
+            35 => Some(Self::OneWireReset),
+            36 => Some(Self::OneWireTransfer),
+            37 => Some(Self::OneWireSearch),
+            38 => Some(Self::OneWireTemperature),
+            39 => Some(Self::I2cTargetStart),
+            40 => Some(Self::I2cTargetLoad),
+            41 => Some(Self::I2cTargetDump),
+            42 => Some(Self::I2cTargetEvents),
+            43 => Some(Self::I2cTargetStop),
+            44 => Some(Self::SpiTargetStart),
+            45 => Some(Self::SpiTargetLoad),
+            46 => Some(Self::SpiTargetLog),
+            47 => Some(Self::SpiTargetStop),
+            48 => Some(Self::Reboot),
+            49 => Some(Self::IdentitySet),
+            50 => Some(Self::SafeStateMode),
+            51 => Some(Self::ProtocolSelect),
+            _  => None
+        }
+    }
+
+    /// True if the command answers with data, such commands are not allowed
+    /// in a batch
+    pub fn is_data(&self) -> bool {
+        matches!(self,
+            Self::CaptureRead
+            | Self::OneWireTransfer
+            | Self::OneWireSearch
+            | Self::I2cTargetDump
+            | Self::I2cTargetEvents
+            | Self::SpiTargetLog
+        )
+    }
+}
+
+/// Represents a command from the host
+#[derive(Deserialize, Debug)]
+pub struct Command {
+    /// Command code as u8
+    pub cod: u8,
+
+    /// id of targetted pin (X => gpioX)
+    pub pin: u8,
+
+    /// argument value
+    pub arg: u8,
+
+    /// optional extended value (durations, timeouts...)
+    pub val: Option<u32>,
+
+    /// optional extended parameters, their meaning depends on the command
+    pub prm: Option<Vec<i32, MAX_PRM_SIZE>>,
+
+    /// optional data encoded in base64
+    pub dat: Option<String<MAX_CMD_DAT_SIZE>>,
+
+    /// optional CRC-16 (CCITT-FALSE) over the command payload
+    pub crc: Option<u16>,
+}
+
+impl Command {
+    /// Compute the CRC-16 of the command payload
+    ///
+    /// The payload is the sequence of bytes [cod, pin, arg] followed by the
+    /// little endian bytes of val and of each prm entry, then the ascii bytes
+    /// of dat, when present
+    pub fn payload_crc(&self) -> u16 {
+        let mut crc = Crc16::new();
+        crc.update(&[self.cod, self.pin, self.arg]);
+        if let Some(val) = self.val {
+            crc.update(&val.to_le_bytes());
+        }
+        if let Some(prm) = &self.prm {
+            for p in prm.iter() {
+                crc.update(&p.to_le_bytes());
+            }
+        }
+        if let Some(dat) = &self.dat {
+            crc.update(dat.as_bytes());
+        }
+        crc.finish()
+    }
+
+    /// Get the extended parameter at the given index
+    pub fn prm_at(&self, index: usize) -> Option<i32> {
+        match &self.prm {
+            Some(prm) => prm.get(index).copied(),
+            None      => None,
+        }
+    }
+}
+
+/// Represents an operation of a batch
+///
+/// Same fields as a command without the data, which would put 16 data
+/// strings on the stack for each batch parsed
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BatchCommand {
+    /// Command code as u8
+    pub cod: u8,
+
+    /// id of targetted pin (X => gpioX)
+    pub pin: u8,
+
+    /// argument value
+    pub arg: u8,
+
+    /// optional extended value (durations, timeouts...)
+    pub val: Option<u32>,
+
+    /// optional extended parameters, their meaning depends on the command
+    pub prm: Option<Vec<i32, MAX_PRM_SIZE>>,
+
+    /// optional CRC-16 (CCITT-FALSE) over the command payload
+    pub crc: Option<u16>,
+}
+
+impl From<&BatchCommand> for Command {
+    fn from(op: &BatchCommand) -> Self {
+        Self {
+            cod: op.cod,
+            pin: op.pin,
+            arg: op.arg,
+            val: op.val,
+            prm: op.prm.clone(),
+            dat: None,
+            crc: op.crc,
+        }
+    }
+}
+
+/// Represents a batch of commands from the host
+///
+/// Operations are executed back-to-back, in order
+#[derive(Deserialize, Debug)]
+pub struct Batch {
+    /// list of operations
+    pub bat: Vec<BatchCommand, MAX_BATCH_SIZE>,
+}
+
+// ============================================================================
+
+/// Type for anwser text
+pub type AnswerText = String<MAX_MSG_SIZE>;
+
+/// Answer status code
+#[derive(Serialize_repr, Debug)]
+#[repr(u8)]
+pub enum AnswerStatus {
+    Ok    = 0u8,
+    Error = 1u8
+}
+
+/// Represenattion of an answer
+#[derive(Serialize, Debug)]
+pub struct Answer {
+    /// Status code
+    pub sts: AnswerStatus,
+
+    /// ID of target pin (X => gpioX)
+    pub pin: u8,
+
+    /// Argument value
+    pub arg: u8,
+
+    /// Text message
+    pub msg: AnswerText,
+
+    /// Optional extended value (measured durations...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub val: Option<u32>,
+
+    /// Optional extended results, their meaning depends on the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prm: Option<Vec<i32, MAX_PRM_SIZE>>,
+}
+
+impl Answer {
+    pub fn ok(pin: u8, arg: u8, msg: AnswerText) -> Self {
+        Self {
+            sts: AnswerStatus::Ok,
+            pin,
+            arg,
+            msg,
+            val: None,
+            prm: None,
+        }
+    }
+
+    pub fn error(pin: u8, arg: u8, msg: AnswerText) -> Self {
+        Self {
+            sts: AnswerStatus::Error,
+            pin,
+            arg,
+            msg,
+            val: None,
+            prm: None,
+        }
+    }
+
+    pub fn with_val(mut self, val: u32) -> Self {
+        self.val = Some(val);
+        self
+    }
+
+    pub fn with_prm(mut self, prm: &[i32]) -> Self {
+        self.prm = Vec::from_slice(prm).ok();
+        self
+    }
+
+    pub fn is_ok(&self) -> bool {
+        match self.sts {
+            AnswerStatus::Ok    => true,
+            AnswerStatus::Error => false,
+        }
+    }
+}
+
+/// Representation of a batch answer
+#[derive(Serialize, Debug)]
+pub struct BatchAnswer {
+    /// Status code, Ok only if every operation succeeded
+    pub sts: AnswerStatus,
+
+    /// Results of the executed operations, in order
+    pub res: Vec<Answer, MAX_BATCH_SIZE>,
+}
+
+/// Type for answer data (base64)
+pub type AnswerData = String<MAX_DAT_SIZE>;
+
+/// Representation of an answer carrying data
+#[derive(Serialize, Debug)]
+pub struct DataAnswer {
+    /// Status code
+    pub sts: AnswerStatus,
+
+    /// ID of target pin (X => gpioX)
+    pub pin: u8,
+
+    /// Argument value
+    pub arg: u8,
+
+    /// Text message
+    pub msg: AnswerText,
+
+    /// Extended value
+    pub val: u32,
+
+    /// Data encoded in base64
+    pub dat: AnswerData,
+}
+
+impl From<Answer> for DataAnswer {
+    /// Carry an answer without data, mostly errors
+    fn from(answer: Answer) -> Self {
+        Self {
+            sts: answer.sts,
+            pin: answer.pin,
+            arg: answer.arg,
+            msg: answer.msg,
+            val: answer.val.unwrap_or(0),
+            dat: AnswerData::new(),
+        }
+    }
+}
+
+/// Representation of the discovery answer
+#[derive(Serialize, Debug)]
+pub struct InfoAnswer {
+    /// Status code
+    pub sts: AnswerStatus,
+
+    /// ID of target pin (X => gpioX)
+    pub pin: u8,
+
+    /// Argument value
+    pub arg: u8,
+
+    /// Text message
+    pub msg: AnswerText,
+
+    /// User label of the device, empty if not set
+    pub lbl: String<MAX_LABEL_SIZE>,
+
+    /// Usb serial number of the device
+    pub ser: String<MAX_SERIAL_SIZE>,
+}
+
+/// Any message sent back to the host
+///
+/// The batch answer is much larger than the other ones, it stays inline as
+/// the responses are built on the stack and serialized at once.
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Response {
+    Single(Answer),
+    Batch(BatchAnswer),
+    Data(DataAnswer),
+    Info(InfoAnswer),
+
+    /// Reply of the SCPI front end, written as plain text
+    #[serde(skip_serializing)]
+    Scpi(ScpiReply),
+}
+
+impl Response {
+    /// Serialize the response in json, returns the size used in `buffer`
+    ///
+    /// A batch of long answers may not fit: it is replaced by an error answer,
+    /// so that the host always gets an answer.
+    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
+        match serde_json_core::to_slice(self, buffer) {
+            Ok(size) => size,
+            Err(_)   => {
+                let answer = Answer::error(0, 0, AnswerText::from_str("Answer too long").unwrap());
+                serde_json_core::to_slice(&Response::Single(answer), buffer).unwrap_or(0)
+            },
+        }
+    }
+}
+
+// ============================================================================
+
+/// Kinds of the frames on the vendor bulk interface
+#[cfg(feature = "vendor")]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Command or batch line, answered with the json response
+    Json        = 1u8,
+
+    /// Capture chunks request, answered with one frame per chunk
+    CaptureRead = 2u8,
+
+    /// Raw pattern steps, answered with a json answer
+    PatternLoad = 3u8,
+}
+
+#[cfg(feature = "vendor")]
+impl FrameKind {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            1 => Some(Self::Json),
+            2 => Some(Self::CaptureRead),
+            3 => Some(Self::PatternLoad),
+            _ => None
+        }
+    }
+}
+
+// ============================================================================
+
+/// Possible argument values for pin output value
+pub enum CmdPinWriteValue {
+    Low,
+    High
+}
+
+impl CmdPinWriteValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Low),
+            1 => Some(Self::High),
+            _ => None,
+        }
+    }
+}
+
+
+/// Possible argument values for pin direction value
+pub enum CmdPinDirValue {
+    PullUpInput,
+    PullDownInput,
+    ReadableOutput,
+}
+
+impl CmdPinDirValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::PullUpInput),
+            1 => Some(Self::PullDownInput),
+            2 => Some(Self::ReadableOutput),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for pulse polarity
+pub enum CmdPulsePolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+impl CmdPulsePolarity {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::ActiveHigh),
+            1 => Some(Self::ActiveLow),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for counted edges
+pub enum CmdCounterEdgeValue {
+    Rising,
+    Falling,
+}
+
+impl CmdCounterEdgeValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Rising),
+            1 => Some(Self::Falling),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for capture trigger
+pub enum CmdCaptureTriggerValue {
+    Immediate,
+    Rising,
+    Falling,
+    Pattern,
+}
+
+impl CmdCaptureTriggerValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Immediate),
+            1 => Some(Self::Rising),
+            2 => Some(Self::Falling),
+            3 => Some(Self::Pattern),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for pattern timing
+pub enum CmdPatternTimingValue {
+    Durations,
+    FixedRate,
+}
+
+impl CmdPatternTimingValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Durations),
+            1 => Some(Self::FixedRate),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for led color format
+pub enum CmdLedFormatValue {
+    Grb,
+    Grbw,
+}
+
+impl CmdLedFormatValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Grb),
+            1 => Some(Self::Grbw),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for servo targets
+pub enum CmdServoTargetValue {
+    Width,
+    Angle,
+}
+
+impl CmdServoTargetValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Width),
+            1 => Some(Self::Angle),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for stepper moves
+pub enum CmdStepperMoveValue {
+    Absolute,
+    Relative,
+}
+
+impl CmdStepperMoveValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Absolute),
+            1 => Some(Self::Relative),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for stepper stops
+pub enum CmdStepperStopValue {
+    Decelerate,
+    Immediate,
+}
+
+impl CmdStepperStopValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Decelerate),
+            1 => Some(Self::Immediate),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for reboot
+pub enum CmdRebootValue {
+    Soft,
+    Bootloader,
+}
+
+impl CmdRebootValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Soft),
+            1 => Some(Self::Bootloader),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for the identity field
+pub enum CmdIdentityValue {
+    Label,
+    Serial,
+}
+
+impl CmdIdentityValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Label),
+            1 => Some(Self::Serial),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for the safe state mode
+pub enum CmdSafeStateValue {
+    Disabled,
+    Enabled,
+}
+
+impl CmdSafeStateValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Disabled),
+            1 => Some(Self::Enabled),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for the protocol selection
+pub enum CmdProtocolValue {
+    Json,
+    Scpi,
+}
+
+impl CmdProtocolValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Json),
+            1 => Some(Self::Scpi),
+            _ => None
+        }
+    }
+}
+
+
+/// Possible argument values for crc mode
+pub enum CmdCrcModeValue {
+    Optional,
+    Strict,
+}
+
+impl CmdCrcModeValue {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Self::Optional),
+            1 => Some(Self::Strict),
+            _ => None
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,261 @@
+// ============================================================================
+
+// Algos
+use heapless::{Deque, String, Vec};
+use core::fmt::Write;
+
+use super::protocol::{Command, CommandCode};
+
+// ============================================================================
+
+/// Max length of a reply line
+pub const MAX_REPLY_SIZE: usize = 256;
+
+/// Type for the reply lines
+pub type ScpiReply = String<MAX_REPLY_SIZE>;
+
+/// Max number of errors kept in the queue
+const ERROR_QUEUE_SIZE: usize = 8;
+
+/// Max length of an error description, longer ones are truncated
+const MAX_ERROR_MSG_SIZE: usize = 64;
+
+/// Standard error codes
+pub const ERR_SYNTAX: i16 = -102;
+pub const ERR_DATA_TYPE: i16 = -104;
+pub const ERR_MISSING_PARAMETER: i16 = -109;
+pub const ERR_UNDEFINED_HEADER: i16 = -113;
+pub const ERR_EXECUTION: i16 = -200;
+pub const ERR_ILLEGAL_PARAMETER: i16 = -224;
+const ERR_QUEUE_OVERFLOW: i16 = -350;
+
+/// Requests of a SCPI program message unit
+pub enum ScpiRequest {
+    /// *IDN?
+    Identify,
+
+    /// *RST
+    Reset,
+
+    /// *CLS
+    ClearStatus,
+
+    /// *OPC?
+    OperationComplete,
+
+    /// SYSTem:ERRor[:NEXT]?
+    ErrorQuery,
+
+    /// SYSTem:PROTocol JSON
+    SelectJson,
+
+    /// Mapped on a command of the json protocol, the answer is returned
+    /// for the queries
+    Command {
+        code: CommandCode,
+        pin: u8,
+        arg: u8,
+        query: ScpiQuery,
+    },
+}
+
+/// Reply expected from a mapped command
+pub enum ScpiQuery {
+    /// Not a query
+    None,
+
+    /// The level in the arg field of the answer
+    Level,
+}
+
+/// Error of a program message unit
+pub struct ScpiError {
+    /// Standard error code
+    pub code: i16,
+
+    /// Description
+    pub msg: String<MAX_ERROR_MSG_SIZE>,
+}
+
+impl ScpiError {
+    pub fn new(code: i16, msg: &str) -> Self {
+        let mut text = String::new();
+        for c in msg.chars() {
+            if text.push(c).is_err() {
+                break;
+            }
+        }
+        Self {
+            code,
+            msg: text,
+        }
+    }
+}
+
+// ============================================================================
+
+/// SCPI text front end
+///
+/// Supports the common commands *IDN?, *RST, *CLS, *OPC?, and:
+///
+/// - `DIGital:PIN<n>:DIRection IN|INPU|INPD|OUT`
+/// - `DIGital:PIN<n> 0|1|OFF|ON` and `DIGital:PIN<n>?`
+/// - `SYSTem:ERRor[:NEXT]?`
+/// - `SYSTem:PROTocol JSON`
+///
+/// The mnemonics take their short or long form, in any case. Several units
+/// are separated by ';', each one with its full header path.
+pub struct ScpiParser {
+    /// Error queue, oldest first
+    errors: Deque<ScpiError, ERROR_QUEUE_SIZE>,
+}
+
+// ============================================================================
+
+impl ScpiParser {
+    /// Create a parser with an empty error queue
+    pub fn new() -> Self {
+        Self {
+            errors: Deque::new(),
+        }
+    }
+
+    /// Parse a program message unit
+    pub fn parse(unit: &str) -> Result<ScpiRequest, ScpiError> {
+        let unit = unit.trim();
+        let (header, param) = match unit.find(|c: char| c.is_ascii_whitespace()) {
+            Some(i) => (&unit[..i], Some(unit[i..].trim())),
+            None    => (unit, None),
+        };
+        let (header, query) = match header.strip_suffix('?') {
+            Some(h) => (h, true),
+            None    => (header, false),
+        };
+
+        if let Some(common) = header.strip_prefix('*') {
+            return match query {
+                true  if common.eq_ignore_ascii_case("IDN") => Ok(ScpiRequest::Identify),
+                false if common.eq_ignore_ascii_case("RST") => Ok(ScpiRequest::Reset),
+                false if common.eq_ignore_ascii_case("CLS") => Ok(ScpiRequest::ClearStatus),
+                true  if common.eq_ignore_ascii_case("OPC") => Ok(ScpiRequest::OperationComplete),
+                _ => Err(ScpiError::new(ERR_UNDEFINED_HEADER, "Undefined header")),
+            };
+        }
+
+        let mut nodes: Vec<&str, 4> = Vec::new();
+        for node in header.trim_start_matches(':').split(':') {
+            nodes.push(node).map_err(|_| ScpiError::new(ERR_UNDEFINED_HEADER, "Undefined header"))?;
+        }
+
+        match nodes.as_slice() {
+            [sys, err] | [sys, err, _] if mnemonic(sys, "SYSTEM", 4) && mnemonic(err, "ERROR", 3) => {
+                if nodes.len() == 3 && !mnemonic(nodes[2], "NEXT", 4) {
+                    return Err(ScpiError::new(ERR_UNDEFINED_HEADER, "Undefined header"));
+                }
+                match query {
+                    true  => Ok(ScpiRequest::ErrorQuery),
+                    false => Err(ScpiError::new(ERR_SYNTAX, "Query only")),
+                }
+            },
+
+            [sys, prot] if mnemonic(sys, "SYSTEM", 4) && mnemonic(prot, "PROTOCOL", 4) => {
+                match param {
+                    Some(p) if p.eq_ignore_ascii_case("JSON") => Ok(ScpiRequest::SelectJson),
+                    Some(_) => Err(ScpiError::new(ERR_ILLEGAL_PARAMETER, "Illegal parameter value")),
+                    None    => Err(ScpiError::new(ERR_MISSING_PARAMETER, "Missing parameter")),
+                }
+            },
+
+            [dig, pin] if mnemonic(dig, "DIGITAL", 3) => {
+                let pin = pin_suffix(pin)?;
+                match query {
+                    true  => Ok(ScpiRequest::Command { code: CommandCode::ReadValue, pin, arg: 0, query: ScpiQuery::Level }),
+                    false => {
+                        let level = match param {
+                            Some(p) if p == "0" || p.eq_ignore_ascii_case("OFF") => 0,
+                            Some(p) if p == "1" || p.eq_ignore_ascii_case("ON")  => 1,
+                            Some(_) => return Err(ScpiError::new(ERR_DATA_TYPE, "Expected 0, 1, OFF or ON")),
+                            None    => return Err(ScpiError::new(ERR_MISSING_PARAMETER, "Missing parameter")),
+                        };
+                        Ok(ScpiRequest::Command { code: CommandCode::WriteValue, pin, arg: level, query: ScpiQuery::None })
+                    },
+                }
+            },
+
+            [dig, pin, dir] if mnemonic(dig, "DIGITAL", 3) && mnemonic(dir, "DIRECTION", 3) && !query => {
+                let pin = pin_suffix(pin)?;
+                // Same values as the set direction command
+                let mode = match param {
+                    Some(p) if p.eq_ignore_ascii_case("INPU") => 0,
+                    Some(p) if p.eq_ignore_ascii_case("INPD") => 1,
+                    Some(p) if p.eq_ignore_ascii_case("IN")   => 1,
+                    Some(p) if p.eq_ignore_ascii_case("OUT")  => 2,
+                    Some(_) => return Err(ScpiError::new(ERR_ILLEGAL_PARAMETER, "Expected IN, INPU, INPD or OUT")),
+                    None    => return Err(ScpiError::new(ERR_MISSING_PARAMETER, "Missing parameter")),
+                };
+                Ok(ScpiRequest::Command { code: CommandCode::SetDirection, pin, arg: mode, query: ScpiQuery::None })
+            },
+
+            _ => Err(ScpiError::new(ERR_UNDEFINED_HEADER, "Undefined header")),
+        }
+    }
+
+    /// Queue an error, the last entry reports an overflow when full
+    pub fn push_error(&mut self, error: ScpiError) {
+        if self.errors.len() + 1 < ERROR_QUEUE_SIZE {
+            self.errors.push_back(error).ok();
+        } else if self.errors.len() + 1 == ERROR_QUEUE_SIZE {
+            self.errors.push_back(ScpiError::new(ERR_QUEUE_OVERFLOW, "Queue overflow")).ok();
+        }
+    }
+
+    /// Pop the oldest error in the SCPI format
+    pub fn pop_error(&mut self, reply: &mut ScpiReply) {
+        match self.errors.pop_front() {
+            Some(error) => write!(reply, "{},\"{}\"", error.code, error.msg).ok(),
+            None        => write!(reply, "0,\"No error\"").ok(),
+        };
+    }
+
+    /// Drop the queued errors
+    pub fn clear(&mut self) {
+        self.errors.clear();
+    }
+}
+
+impl Default for ScpiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+
+/// True if the word is the long or the short form of the mnemonic
+fn mnemonic(word: &str, long: &str, short_len: usize) -> bool {
+    word.eq_ignore_ascii_case(long) || word.eq_ignore_ascii_case(&long[..short_len])
+}
+
+/// Get the number of a PIN<n> node
+fn pin_suffix(node: &str) -> Result<u8, ScpiError> {
+    let digits = node.find(|c: char| c.is_ascii_digit()).unwrap_or(node.len());
+    if !mnemonic(&node[..digits], "PIN", 3) {
+        return Err(ScpiError::new(ERR_UNDEFINED_HEADER, "Undefined header"));
+    }
+    node[digits..].parse::<u8>().map_err(|_| ScpiError::new(ERR_UNDEFINED_HEADER, "Missing pin number"))
+}
+
+/// Build a command of the json protocol
+pub fn command(code: CommandCode, pin: u8, arg: u8) -> Command {
+    Command {
+        cod: code as u8,
+        pin,
+        arg,
+        val: None,
+        prm: None,
+        dat: None,
+        crc: None,
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,196 @@
+// ============================================================================
+
+use super::pins::{PinBackend, PinError, PinMode};
+
+// ============================================================================
+
+/// Number of gpios of the RP2040
+pub const SIM_PIN_COUNT: usize = 30;
+
+/// State of a simulated pin
+#[derive(Clone, Copy)]
+struct SimPin {
+    /// Current mode, the pins start as pull-down inputs
+    mode: PinMode,
+
+    /// Level driven in output mode
+    level: bool,
+
+    /// Level forced from outside of the device, if any
+    external: Option<bool>,
+
+    /// Net of the pin, the pins of the same net are wired together
+    net: u8,
+}
+
+// ============================================================================
+
+/// Simulated gpios, with the same pins as the device
+///
+/// Pins can be wired together: an input reads the level of the first output
+/// of its net, else the level forced from outside, else its pull.
+pub struct SimPins {
+    pins: [SimPin; SIM_PIN_COUNT],
+}
+
+// ============================================================================
+
+impl SimPins {
+    /// Create the pins as pull down inputs, none of them wired
+    pub fn new() -> Self {
+        let mut pins = [SimPin {
+            mode: PinMode::PullDownInput,
+            level: false,
+            external: None,
+            net: 0,
+        }; SIM_PIN_COUNT];
+        for (idx, pin) in pins.iter_mut().enumerate() {
+            pin.net = idx as u8;
+        }
+        Self {
+            pins,
+        }
+    }
+
+    /// Wire two pins together, with the pins already wired to them
+    pub fn connect(&mut self, a: u8, b: u8) -> Result<(), PinError> {
+        if !self.is_valid(a) || !self.is_valid(b) {
+            return Err(PinError::InvalidPin);
+        }
+        let (net_a, net_b) = (self.pins[a as usize].net, self.pins[b as usize].net);
+        for pin in self.pins.iter_mut().filter(|p| p.net == net_b) {
+            pin.net = net_a;
+        }
+        Ok(())
+    }
+
+    /// Force the level seen by the inputs of the net, `None` releases it
+    pub fn set_external(&mut self, idx: u8, level: Option<bool>) -> Result<(), PinError> {
+        if !self.is_valid(idx) {
+            return Err(PinError::InvalidPin);
+        }
+        self.pins[idx as usize].external = level;
+        Ok(())
+    }
+
+    /// Current mode of the pin
+    pub fn mode(&self, idx: u8) -> Option<PinMode> {
+        match self.is_valid(idx) {
+            true  => Some(self.pins[idx as usize].mode),
+            false => None,
+        }
+    }
+
+    /// Level of the pin as seen from outside
+    pub fn level(&self, idx: u8) -> Option<bool> {
+        match self.is_valid(idx) {
+            true  => Some(self.net_level(idx)),
+            false => None,
+        }
+    }
+
+    // ------------------------------------------------------------------------
+
+    /// Resolve the level of the net of the pin
+    fn net_level(&self, idx: u8) -> bool {
+        let pin = &self.pins[idx as usize];
+        if pin.mode == PinMode::Output {
+            return pin.level;
+        }
+
+        let net = self.pins.iter().filter(|p| p.net == pin.net);
+        if let Some(driver) = net.clone().find(|p| p.mode == PinMode::Output) {
+            return driver.level;
+        }
+        if let Some(level) = net.filter_map(|p| p.external).next() {
+            return level;
+        }
+        pin.mode == PinMode::PullUpInput
+    }
+}
+
+impl Default for SimPins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============================================================================
+
+impl PinBackend for SimPins {
+    fn is_valid(&self, idx: u8) -> bool {
+        // Same pins as the GpioController of the device
+        matches!(idx, 0..=22 | 25)
+    }
+
+    fn set_mode(&mut self, idx: u8, mode: PinMode) -> Result<(), PinError> {
+        if !self.is_valid(idx) {
+            return Err(PinError::InvalidPin);
+        }
+        self.pins[idx as usize].mode = mode;
+        Ok(())
+    }
+
+    fn write(&mut self, idx: u8, level: bool) -> Result<(), PinError> {
+        match self.mode(idx) {
+            Some(PinMode::Output) => {
+                self.pins[idx as usize].level = level;
+                Ok(())
+            },
+            Some(_) => Err(PinError::InvalidMode),
+            None    => Err(PinError::InvalidPin),
+        }
+    }
+
+    fn read(&mut self, idx: u8) -> Result<bool, PinError> {
+        self.level(idx).ok_or(PinError::InvalidPin)
+    }
+}
+
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_read_their_pull() {
+        let mut pins = SimPins::new();
+        pins.set_mode(2, PinMode::PullUpInput).unwrap();
+        assert!(pins.read(2).unwrap());
+        pins.set_mode(2, PinMode::PullDownInput).unwrap();
+        assert!(!pins.read(2).unwrap());
+    }
+
+    #[test]
+    fn wired_input_follows_the_output() {
+        let mut pins = SimPins::new();
+        pins.connect(3, 4).unwrap();
+        pins.set_mode(3, PinMode::Output).unwrap();
+        pins.set_mode(4, PinMode::PullUpInput).unwrap();
+
+        pins.write(3, false).unwrap();
+        assert!(!pins.read(4).unwrap());
+        pins.write(3, true).unwrap();
+        assert!(pins.read(4).unwrap());
+    }
+
+    #[test]
+    fn external_level_overrides_the_pull() {
+        let mut pins = SimPins::new();
+        pins.set_mode(5, PinMode::PullDownInput).unwrap();
+        pins.set_external(5, Some(true)).unwrap();
+        assert!(pins.read(5).unwrap());
+        pins.set_external(5, None).unwrap();
+        assert!(!pins.read(5).unwrap());
+    }
+
+    #[test]
+    fn write_needs_an_output() {
+        let mut pins = SimPins::new();
+        assert!(matches!(pins.write(6, true), Err(PinError::InvalidMode)));
+        assert!(matches!(pins.write(23, true), Err(PinError::InvalidPin)));
+    }
+}
+
+// ============================================================================
@@ -30,6 +30,7 @@ class DriverPicohaIO(MetaDriverIo):
                 "usbid_vendor":  "[optional] Usb vendor ID in the following format (\"16c0\" : default)",
                 "usbid_product": "[optional] Usb product ID in the following format (\"05e1\" : default)",
                 "usbid_serial":  "[optional] Usb serial ID",
+                "serial_port":   "[optional] Serial port to use instead of the usb IDs (simulator)",
             }
         }
 
@@ -47,6 +48,7 @@ class DriverPicohaIO(MetaDriverIo):
         self.usbid_vendor = "16c0"
         self.usbid_product = "05e1"
         self.usbid_serial = None
+        self.serial_port = None
 
         # Import settings
         if "settings" in tree:
@@ -61,9 +63,13 @@ class DriverPicohaIO(MetaDriverIo):
                 self.usbid_product = settings["usbid_product"]
             if "usbid_serial" in settings:
                 self.usbid_serial = settings["usbid_serial"]
+            if "serial_port" in settings:
+                self.serial_port = settings["serial_port"]
 
         # 
         usb_uuid = self.usbid_vendor + self.usbid_product
+        if self.serial_port is not None:
+            usb_uuid = self.serial_port
         self.log.debug(f"usb_uuid = {usb_uuid}")
 
         # Register commands
@@ -72,7 +78,7 @@ class DriverPicohaIO(MetaDriverIo):
 
         # Init the bridge
         if usb_uuid not in DriverPicohaIO.Bridges:
-            DriverPicohaIO.Bridges[usb_uuid] = PicohaBridge(usb_uuid, self.usbid_vendor, self.usbid_product, self.usbid_serial)
+            DriverPicohaIO.Bridges[usb_uuid] = PicohaBridge(usb_uuid, self.usbid_vendor, self.usbid_product, self.usbid_serial, self.serial_port)
         self.bridge = DriverPicohaIO.Bridges[usb_uuid]
 
     ###########################################################################
@@ -37,9 +37,13 @@ class PicohaBridge:
     ###########################################################################
     ###########################################################################
     
-    def __init__(self, usb_uuid, vendor_id, product_id, serial=None):
+    def __init__(self, usb_uuid, vendor_id, product_id, serial=None, port=None):
         """Constructor
+
+        Args:
+            port (str): serial port to use instead of the usb lookup (simulator)
         """
+        self.port = port
         self.usbid_vendor = vendor_id
         self.usbid_product = product_id
         self.usbid_serial = serial
@@ -59,7 +63,10 @@ class PicohaBridge:
         """Initial state, configure the serial port
         """
         # Get serial port
-        self.serial_port = TTYPortfromUsbInfo(self.usbid_vendor, self.usbid_product, self.usbid_serial, base_dev_tty="/dev/ttyACM")
+        if self.port is not None:
+            self.serial_port = self.port
+        else:
+            self.serial_port = TTYPortfromUsbInfo(self.usbid_vendor, self.usbid_product, self.usbid_serial, base_dev_tty="/dev/ttyACM")
         if self.serial_port is None:
             self.log.error(f"adapter not connected !")
             self.fsm.init_fail()
@@ -74,7 +81,7 @@ class PicohaBridge:
             self.serial_obj.write( (json.dumps(req) + "\n") .encode() )
             ret = json.loads(self.serial_obj.readline())
             if "sts" in ret and ret["sts"] == 0:
-                self.log.debug(f"Connected to pico probe SUCCESS !")
+                self.log.debug(f"Connected to pico probe SUCCESS ! (label: '{ret.get('lbl', '')}', serial: '{ret.get('ser', '')}')")
             else:
                 raise Exception("ERROR on the probe connection !")
 
@@ -32,6 +32,8 @@ def before_tag(context, tag):
     #
     elif tag.startswith(ACTION_PLATFORM_START):
         treefile = tag.replace(ACTION_PLATFORM_START, "")
+        # Allow to run the features on another tree, the simulator one for example
+        treefile = context.config.userdata.get("platform_tree", treefile)
         platform_start(context, treefile)
     elif tag.startswith("action.platform_close"):
         # name = tag.replace("action.client.", "")
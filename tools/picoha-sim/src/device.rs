@@ -0,0 +1,127 @@
+// ============================================================================
+
+// Std
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Command processing of the firmware
+use picoha_io::MANUFACTURER_NAME;
+use picoha_io::processor::{CommandProcessor, ProcessorState};
+use picoha_io::processor::pins::{PinBackend, PinMode};
+use picoha_io::processor::protocol::{Answer, AnswerStatus, AnswerText, Command, CommandCode};
+use picoha_io::processor::protocol::{DataAnswer, InfoAnswer};
+use picoha_io::processor::scpi::ScpiReply;
+use picoha_io::processor::sim_pins::{SimPins, SIM_PIN_COUNT};
+
+// ============================================================================
+
+/// Product reported by the identification query when no label is set
+const PRODUCT_NAME: &str = "picoha-io-sim";
+
+// ============================================================================
+
+/// Simulated device
+///
+/// Runs the `processor` module of the firmware on simulated pins, not its
+/// `application` module. The commands tied to the RP2040 peripherals (pio,
+/// dma, pwm, i2c, spi, flash...) answer an error.
+pub struct SimDevice {
+    /// Command processing state
+    commands: ProcessorState,
+
+    /// Simulated gpios
+    pins: SimPins,
+
+    /// Time origin of the simulated timer
+    start: Instant,
+
+    /// Label returned by the test command
+    label: heapless::String<32>,
+
+    /// Serial number returned by the test command
+    serial: heapless::String<32>,
+}
+
+// ============================================================================
+
+impl SimDevice {
+    /// Create the device on the given pins, with its usb label and serial
+    ///
+    /// Fails when the label or the serial does not fit.
+    pub fn new(pins: SimPins, label: &str, serial: &str) -> Result<Self, &'static str> {
+        Ok(Self {
+            commands: ProcessorState::new(),
+            pins,
+            start: Instant::now(),
+            label: heapless::String::from_str(label).map_err(|_| "label too long")?,
+            serial: heapless::String::from_str(serial).map_err(|_| "serial too long")?,
+        })
+    }
+}
+
+// ============================================================================
+
+impl CommandProcessor for SimDevice {
+    type Pins = SimPins;
+
+    fn state(&self) -> &ProcessorState {
+        &self.commands
+    }
+
+    fn state_mut(&mut self) -> &mut ProcessorState {
+        &mut self.commands
+    }
+
+    fn pins(&mut self) -> &mut SimPins {
+        &mut self.pins
+    }
+
+    fn now_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(Duration::from_micros(us as u64));
+    }
+
+    fn apply_safe_state(&mut self) {
+        for pin in 0..SIM_PIN_COUNT as u8 {
+            self.pins.set_mode(pin, PinMode::PullDownInput).ok();
+        }
+    }
+
+    fn process_test(&self) -> InfoAnswer {
+        InfoAnswer {
+            sts: AnswerStatus::Ok,
+            pin: 0,
+            arg: 1,
+            msg: AnswerText::new(),
+            lbl: heapless::String::from_str(&self.label).unwrap(),
+            ser: heapless::String::from_str(&self.serial).unwrap(),
+        }
+    }
+
+    fn write_identification(&self, reply: &mut ScpiReply) {
+        let product = match self.label.is_empty() {
+            true  => PRODUCT_NAME,
+            false => self.label.as_str(),
+        };
+        write!(reply, "{},{},{},{}",
+            MANUFACTURER_NAME,
+            product,
+            self.serial,
+            env!("CARGO_PKG_VERSION"),
+        ).ok();
+    }
+
+    fn execute_device_command(&mut self, _code: CommandCode, cmd: &Command) -> Answer {
+        Answer::error(cmd.pin, cmd.arg, AnswerText::from_str("Not available in the simulator").unwrap())
+    }
+
+    fn process_data_command(&mut self, _code: CommandCode, cmd: &Command) -> DataAnswer {
+        DataAnswer::from(Answer::error(cmd.pin, cmd.arg, AnswerText::from_str("Not available in the simulator").unwrap()))
+    }
+}
+
+// ============================================================================
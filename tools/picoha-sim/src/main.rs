@@ -0,0 +1,190 @@
+//! Host simulator of the io firmware
+//!
+//! Runs the command processing of the firmware on simulated gpios and serves
+//! it on a pseudo-terminal: same json protocol, same SCPI front end, same
+//! answers. Pins can be wired together to emulate the loopback connections
+//! of the test bench.
+//!
+//! ```bash
+//! picoha-sim --link /tmp/ttyPICOHA --wire 0:1 --wire 2:3
+//! ```
+
+// ============================================================================
+
+// Std
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+// Command processing of the firmware
+use picoha_io::processor::CommandProcessor;
+use picoha_io::processor::protocol::Response;
+use picoha_io::processor::sim_pins::SimPins;
+
+mod device;
+use device::SimDevice;
+
+mod pty;
+use pty::Pty;
+
+// ============================================================================
+
+const USAGE: &str = "\
+Usage: picoha-sim [OPTIONS]
+
+Options:
+    --wire A:B        Wire gpio A to gpio B, can be repeated
+    --link PATH       Create a symlink to the pseudo-terminal at PATH
+    --label TEXT      Label returned by the test command (default: none)
+    --serial TEXT     Serial number returned by the test command
+                      (default: SIMULATOR)
+    -h, --help        Print this help
+";
+
+/// Command line options
+struct Options {
+    /// Pins wired together
+    wires: Vec<(u8, u8)>,
+
+    /// Symlink to create to the slave side of the pseudo-terminal
+    link: Option<String>,
+
+    /// Label of the simulated device
+    label: String,
+
+    /// Serial number of the simulated device
+    serial: String,
+}
+
+// ============================================================================
+
+/// Parse a wire option, "A:B"
+fn parse_wire(text: &str) -> Result<(u8, u8), String> {
+    let (a, b) = text.split_once(':').ok_or(format!("invalid wire '{}', expected A:B", text))?;
+    let a = a.parse::<u8>().map_err(|_| format!("invalid pin '{}'", a))?;
+    let b = b.parse::<u8>().map_err(|_| format!("invalid pin '{}'", b))?;
+    Ok((a, b))
+}
+
+/// Parse the command line
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        wires: Vec::new(),
+        link: None,
+        label: String::new(),
+        serial: String::from("SIMULATOR"),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--wire"        => options.wires.push(parse_wire(&value()?)?),
+            "--link"        => options.link = Some(value()?),
+            "--label"       => options.label = value()?,
+            "--serial"      => options.serial = value()?,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            },
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+    Ok(options)
+}
+
+// ----------------------------------------------------------------------------
+
+/// Point the link to the pseudo-terminal, replacing a previous link
+fn create_link(link: &str, target: &str) -> io::Result<()> {
+    let path = Path::new(link);
+    if path.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+        std::fs::remove_file(path)?;
+    }
+    symlink(target, path)
+}
+
+/// Serve the device on the pseudo-terminal
+fn run(device: &mut SimDevice, pty: &Pty) -> io::Result<()> {
+    let mut ans_buffer = [0u8; 4096];
+    loop {
+        let mut buf = [0u8; 1024];
+        let count = match pty.read(&mut buf) {
+            Ok(count) => count,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        device.feed_cmd_buffer(&buf, count);
+
+        // Several lines may have come in the same read
+        while let Some(response) = device.update_command_processing() {
+            match response {
+                Response::Scpi(reply) => {
+                    pty.write_all(reply.as_bytes())?;
+                    pty.write_all(b"\n")?;
+                }
+                response => {
+                    // Keep the last byte for the '\n', like on the device
+                    let size = response.serialize(&mut ans_buffer[..4095]);
+                    ans_buffer[size] = b'\n';
+                    pty.write_all(&ans_buffer[0..(size+1)])?;
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("picoha-sim: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Loopback wiring
+    let mut pins = SimPins::new();
+    for (a, b) in options.wires.iter() {
+        if pins.connect(*a, *b).is_err() {
+            eprintln!("picoha-sim: cannot wire gpio {} to gpio {}", a, b);
+            std::process::exit(2);
+        }
+    }
+
+    let mut device = match SimDevice::new(pins, &options.label, &options.serial) {
+        Ok(device) => device,
+        Err(err) => {
+            eprintln!("picoha-sim: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let pty = match Pty::open() {
+        Ok(pty) => pty,
+        Err(err) => {
+            eprintln!("picoha-sim: cannot create the pseudo-terminal: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(link) = options.link.as_ref() {
+        if let Err(err) = create_link(link, pty.slave_path()) {
+            eprintln!("picoha-sim: cannot create the link {}: {}", link, err);
+            std::process::exit(1);
+        }
+    }
+
+    // The path is the only output, scripts can read it
+    println!("{}", pty.slave_path());
+    io::stdout().flush().ok();
+
+    if let Err(err) = run(&mut device, &pty) {
+        eprintln!("picoha-sim: {}", err);
+        std::process::exit(1);
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,110 @@
+// ============================================================================
+
+// Std
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::io::RawFd;
+
+// ============================================================================
+
+/// Pseudo-terminal, the simulator owns the master side
+///
+/// The slave side stays open in the simulator so the master does not see a
+/// hang up each time a host closes the port.
+pub struct Pty {
+    /// Master side, read the host requests, write the answers
+    master: RawFd,
+
+    /// Slave side, kept open
+    slave: RawFd,
+
+    /// Path of the slave side, to open from the host
+    slave_path: String,
+}
+
+// ============================================================================
+
+impl Pty {
+    /// Create a pseudo-terminal in raw mode
+    pub fn open() -> io::Result<Self> {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if master < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::grantpt(master) != 0 || libc::unlockpt(master) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(master);
+                return Err(err);
+            }
+
+            let mut name = [0 as libc::c_char; 128];
+            if libc::ptsname_r(master, name.as_mut_ptr(), name.len()) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(master);
+                return Err(err);
+            }
+            let slave_path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+            let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+            if slave < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(master);
+                return Err(err);
+            }
+
+            // No echo and no line processing, like the usb serial of the device
+            let mut termios: libc::termios = std::mem::zeroed();
+            libc::tcgetattr(slave, &mut termios);
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(slave, libc::TCSANOW, &termios);
+
+            Ok(Self {
+                master,
+                slave,
+                slave_path,
+            })
+        }
+    }
+
+    /// Path of the slave side
+    pub fn slave_path(&self) -> &str {
+        &self.slave_path
+    }
+
+    /// Read the bytes sent by the host, blocks until some are available
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = unsafe { libc::read(self.master, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        match count < 0 {
+            true  => Err(io::Error::last_os_error()),
+            false => Ok(count as usize),
+        }
+    }
+
+    /// Send all the bytes to the host
+    pub fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let count = unsafe { libc::write(self.master, buf.as_ptr() as *const libc::c_void, buf.len()) };
+            if count < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            buf = &buf[count as usize..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.slave);
+            libc::close(self.master);
+        }
+    }
+}
+
+// ============================================================================
@@ -0,0 +1,114 @@
+#!/usr/bin/env python3
+"""Run a logic capture on a picoha-io adapter and export it as a VCD file
+
+The VCD file can be opened with GTKWave or imported by sigrok/PulseView.
+"""
+import json
+import time
+import base64
+import argparse
+import serial
+
+# Command codes
+COD_CAPTURE_START = 16
+COD_CAPTURE_STATUS = 17
+COD_CAPTURE_READ = 18
+
+# Capture states
+STATE_DONE = 2
+
+# Triggers
+TRIGGERS = { "none": 0, "rising": 1, "falling": 2, "pattern": 3 }
+
+###############################################################################
+###############################################################################
+
+def request(port, req):
+    """Send a request and return the parsed answer
+    """
+    port.write((json.dumps(req) + "\n").encode())
+    ans = json.loads(port.readline())
+    if ans["sts"] != 0:
+        raise Exception(f"request {req} failed: {ans['msg']}")
+    return ans
+
+###############################################################################
+###############################################################################
+
+def capture(port, pin_mask, rate, samples, trigger, trigger_pin, pattern, pattern_mask):
+    """Run the capture and return the list of samples (pins shifted to their gpio index)
+    """
+    request(port, { "cod": COD_CAPTURE_START, "pin": trigger_pin, "arg": TRIGGERS[trigger],
+        "val": pattern, "prm": [pin_mask, rate, samples, pattern_mask] })
+
+    # Wait for the end of the capture
+    while True:
+        status = request(port, { "cod": COD_CAPTURE_STATUS, "pin": 0, "arg": 0 })
+        if status["arg"] == STATE_DONE:
+            break
+        time.sleep(0.05)
+
+    captured = status["val"]
+    bytes_per_sample = status["prm"][2]
+    samples_per_chunk = status["prm"][3]
+    low = (pin_mask & -pin_mask).bit_length() - 1
+
+    # Read the chunks
+    data = bytearray()
+    for index in range((captured + samples_per_chunk - 1) // samples_per_chunk):
+        ans = request(port, { "cod": COD_CAPTURE_READ, "pin": 0, "arg": 0, "val": index })
+        data += base64.b64decode(ans["dat"])
+
+    return [
+        int.from_bytes(data[i:i + bytes_per_sample], "little") << low
+        for i in range(0, captured * bytes_per_sample, bytes_per_sample)
+    ]
+
+###############################################################################
+###############################################################################
+
+def write_vcd(filename, samples, pin_mask, rate):
+    """Write the samples in a VCD file, one wire per pin of the mask
+    """
+    pins = [ pin for pin in range(32) if pin_mask & (1 << pin) ]
+    ids = { pin: chr(33 + i) for i, pin in enumerate(pins) }
+    timescale_ns = 1e9 / rate
+
+    with open(filename, "w") as f:
+        f.write("$timescale 1 ns $end\n")
+        f.write("$scope module picoha $end\n")
+        for pin in pins:
+            f.write(f"$var wire 1 {ids[pin]} gpio{pin} $end\n")
+        f.write("$upscope $end\n$enddefinitions $end\n")
+
+        previous = None
+        for index, sample in enumerate(samples):
+            changes = [ pin for pin in pins if previous is None or (sample ^ previous) & (1 << pin) ]
+            if changes:
+                f.write(f"#{round(index * timescale_ns)}\n")
+                for pin in changes:
+                    f.write(f"{(sample >> pin) & 1}{ids[pin]}\n")
+            previous = sample
+        f.write(f"#{round(len(samples) * timescale_ns)}\n")
+
+###############################################################################
+###############################################################################
+
+if __name__ == "__main__":
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("port", help="serial port of the adapter (ex: /dev/ttyACM0)")
+    parser.add_argument("output", help="output VCD file")
+    parser.add_argument("--pins", type=lambda x: int(x, 0), default=0xFF, help="pin mask (default: 0xFF)")
+    parser.add_argument("--rate", type=int, default=1000000, help="sample rate in Hz (default: 1000000)")
+    parser.add_argument("--samples", type=int, default=10000, help="number of samples (default: 10000)")
+    parser.add_argument("--trigger", choices=TRIGGERS.keys(), default="none")
+    parser.add_argument("--trigger-pin", type=int, default=0, help="pin of the edge triggers")
+    parser.add_argument("--pattern", type=lambda x: int(x, 0), default=0, help="value of the pattern trigger")
+    parser.add_argument("--pattern-mask", type=lambda x: int(x, 0), default=0, help="contiguous pins of the pattern trigger")
+    args = parser.parse_args()
+
+    with serial.Serial(args.port, timeout=2) as port:
+        samples = capture(port, args.pins, args.rate, args.samples, args.trigger,
+            args.trigger_pin, args.pattern, args.pattern_mask)
+    write_vcd(args.output, samples, args.pins, args.rate)
+    print(f"{len(samples)} samples written to {args.output}")